@@ -1,10 +1,13 @@
+use crate::game_map::*;
 use crate::game_obj::*;
+use crate::pathfinding::*;
 
 use bevy::prelude::*;
-use rand::*;
-use serde::Deserialize;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
-#[derive(Debug, Resource)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Resource)]
 pub enum Action {
     Move,
     Shoot,
@@ -26,6 +29,7 @@ pub struct AIComponent {
     pub keep_direction_timer: Timer,
     pub move_timer: Timer,
     pub shoot_timer: Timer,
+    pub path: Vec<MapPos>,
 }
 
 impl AIComponent {
@@ -39,37 +43,71 @@ impl AIComponent {
             ),
             move_timer: Timer::from_seconds(ai_config.move_duration, TimerMode::Repeating),
             shoot_timer: Timer::from_seconds(ai_config.shoot_duration, TimerMode::Repeating),
+            path: Vec::new(),
         }
     }
+
+    pub fn get_save_config(&self) -> GameMapAIConfig {
+        GameMapAIConfig {
+            action: self.action,
+            collision_happened: self.collision_happened,
+            keep_direction_elapsed: self.keep_direction_timer.elapsed_secs(),
+            move_elapsed: self.move_timer.elapsed_secs(),
+            shoot_elapsed: self.shoot_timer.elapsed_secs(),
+            path: self.path.clone(),
+        }
+    }
+
+    pub fn load_save_config(&mut self, config: &GameMapAIConfig) {
+        self.action = config.action;
+        self.collision_happened = config.collision_happened;
+        self.keep_direction_timer
+            .set_elapsed(Duration::from_secs_f32(config.keep_direction_elapsed));
+        self.move_timer
+            .set_elapsed(Duration::from_secs_f32(config.move_elapsed));
+        self.shoot_timer
+            .set_elapsed(Duration::from_secs_f32(config.shoot_elapsed));
+        self.path = config.path.clone();
+    }
+}
+
+// The enemy an AI tank goes after
+#[derive(Debug, Clone, Copy)]
+pub struct AITarget {
+    pub pos: Vec2,
+    pub collide_span: f32,
 }
 
 pub fn update_ai_for_obj(
     obj: &mut GameObjInfo,
     ai_comp: &mut AIComponent,
     ai_config: &AIConfig,
-    player_pos: &Vec2,
-    player_collide_span: f32,
+    target: &AITarget,
+    nav_grid: &NavGrid,
+    rng: &mut impl Rng,
     time: &Time,
 ) {
     ai_comp.keep_direction_timer.tick(time.delta());
     match ai_comp.action {
-        None => new_action(obj, ai_comp, ai_config, player_pos, player_collide_span),
+        None => new_action(obj, ai_comp, ai_config, target, nav_grid, rng),
         Some(Action::Move) => {
             ai_comp.move_timer.tick(time.delta());
             if ai_comp.move_timer.is_finished() {
-                new_action(obj, ai_comp, ai_config, player_pos, player_collide_span);
+                new_action(obj, ai_comp, ai_config, target, nav_grid, rng);
             } else if ai_comp.keep_direction_timer.is_finished() {
-                choose_new_direction(obj, ai_comp, player_pos, player_collide_span);
+                chase_player(obj, ai_comp, target, nav_grid, rng);
             } else if ai_comp.collision_happened {
-                choose_alt_direction(obj, ai_comp);
+                choose_alt_direction(obj, ai_comp, rng);
+            } else {
+                follow_path(obj, ai_comp, nav_grid, obj.speed * time.delta_secs());
             }
         }
         Some(Action::Shoot) => {
             ai_comp.shoot_timer.tick(time.delta());
             if ai_comp.shoot_timer.is_finished() {
-                new_action(obj, ai_comp, ai_config, player_pos, player_collide_span);
+                new_action(obj, ai_comp, ai_config, target, nav_grid, rng);
             } else if ai_comp.keep_direction_timer.is_finished() {
-                choose_new_direction(obj, ai_comp, player_pos, player_collide_span);
+                choose_new_direction(obj, ai_comp, target, rng);
             }
         }
     }
@@ -79,31 +117,73 @@ fn new_action(
     obj: &mut GameObjInfo,
     ai_comp: &mut AIComponent,
     ai_config: &AIConfig,
-    player_pos: &Vec2,
-    player_collide_span: f32,
+    target: &AITarget,
+    nav_grid: &NavGrid,
+    rng: &mut impl Rng,
 ) {
-    let mut rng = rand::rng();
-
     let dice = rng.random_range(0.0..1.0) as f32;
     if dice < ai_config.move_prob {
         ai_comp.action = Some(Action::Move);
         ai_comp.move_timer.reset();
         ai_comp.collision_happened = false;
+        chase_player(obj, ai_comp, target, nav_grid, rng);
     } else {
         ai_comp.action = Some(Action::Shoot);
         ai_comp.shoot_timer.reset();
+        choose_new_direction(obj, ai_comp, target, rng);
     }
-
-    choose_new_direction(obj, ai_comp, player_pos, player_collide_span);
 }
 
-fn choose_alt_direction(obj: &mut GameObjInfo, ai_comp: &mut AIComponent) {
-    let c = if rand::rng().random::<bool>() {
-        1.0
-    } else {
-        -1.0
+fn chase_player(
+    obj: &mut GameObjInfo,
+    ai_comp: &mut AIComponent,
+    target: &AITarget,
+    nav_grid: &NavGrid,
+    rng: &mut impl Rng,
+) {
+    let start = nav_grid.get_map_pos(&obj.pos);
+    let goal = nav_grid.get_map_pos(&target.pos);
+
+    let Some(mut path) = nav_grid.find_path(&start, &goal, obj.collide_span) else {
+        ai_comp.path.clear();
+        choose_new_direction(obj, ai_comp, target, rng);
+        return;
     };
 
+    if !nav_grid.is_passable(&start, obj.collide_span) {
+        path.remove(0);
+    }
+    path.reverse();
+    ai_comp.path = path;
+
+    follow_path(obj, ai_comp, nav_grid, 0.0);
+    ai_comp.keep_direction_timer.reset();
+}
+
+fn follow_path(obj: &mut GameObjInfo, ai_comp: &mut AIComponent, nav_grid: &NavGrid, step: f32) {
+    while let Some(next) = ai_comp.path.last() {
+        let delta = nav_grid.cell_center(next) - obj.pos;
+        let (dx, dy) = (delta.x.abs(), delta.y.abs());
+
+        if dx <= step && dy <= step {
+            ai_comp.path.pop();
+            continue;
+        }
+
+        // Line up with the path on the minor axis first so the tank does not
+        // scrape the walls along the corridor it is about to enter.
+        obj.direction = if (dx > dy && dy > step) || dx <= step {
+            Vec2::new(0.0, delta.y.signum())
+        } else {
+            Vec2::new(delta.x.signum(), 0.0)
+        };
+        return;
+    }
+}
+
+fn choose_alt_direction(obj: &mut GameObjInfo, ai_comp: &mut AIComponent, rng: &mut impl Rng) {
+    let c = if rng.random::<bool>() { 1.0 } else { -1.0 };
+
     obj.direction = if obj.direction.x != 0.0 {
         Vec2::new(0.0, c)
     } else {
@@ -118,17 +198,17 @@ fn choose_alt_direction(obj: &mut GameObjInfo, ai_comp: &mut AIComponent) {
 fn choose_new_direction(
     obj: &mut GameObjInfo,
     ai_comp: &mut AIComponent,
-    player_pos: &Vec2,
-    player_collide_span: f32,
+    target: &AITarget,
+    rng: &mut impl Rng,
 ) {
-    obj.direction = if (obj.pos.x - player_pos.x).abs() < player_collide_span {
-        Vec2::new(0.0, (player_pos.y - obj.pos.y).signum())
-    } else if (obj.pos.y - player_pos.y).abs() < player_collide_span {
-        Vec2::new((player_pos.x - obj.pos.x).signum(), 0.0)
-    } else if rand::rng().random::<bool>() {
-        Vec2::new(0.0, (player_pos.y - obj.pos.y).signum())
+    obj.direction = if (obj.pos.x - target.pos.x).abs() < target.collide_span {
+        Vec2::new(0.0, (target.pos.y - obj.pos.y).signum())
+    } else if (obj.pos.y - target.pos.y).abs() < target.collide_span {
+        Vec2::new((target.pos.x - obj.pos.x).signum(), 0.0)
+    } else if rng.random::<bool>() {
+        Vec2::new(0.0, (target.pos.y - obj.pos.y).signum())
     } else {
-        Vec2::new((player_pos.x - obj.pos.x).signum(), 0.0)
+        Vec2::new((target.pos.x - obj.pos.x).signum(), 0.0)
     };
 
     ai_comp.keep_direction_timer.reset();
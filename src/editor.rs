@@ -0,0 +1,472 @@
+use crate::game_lib::*;
+use crate::game_map::*;
+use crate::game_obj::*;
+use crate::grid_map::*;
+use crate::my_error::*;
+use crate::render::*;
+use crate::save::*;
+use crate::systems::*;
+use crate::utils::*;
+
+use bevy::ecs::entity::EntityHashMap;
+use bevy::ecs::system::SystemParam;
+use bevy::input::mouse::AccumulatedMouseScroll;
+use bevy::prelude::*;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+const HELP_TEXT: &str = "Tab/wheel: pick  LMB: place  RMB: delete  R: rotate  Ctrl+S: save";
+
+// The map being edited, with its objects in file order. Objects that the
+// editor cannot show, such as players waiting to respawn, have no entity and
+// are written back as they are on save.
+#[derive(Resource)]
+pub struct EditorMap {
+    pub save_path: PathBuf,
+    pub map_config: GameMapConfig,
+    pub has_grid: bool,
+    pub objs: Vec<(Option<Entity>, GameMapObjConfig)>,
+}
+
+#[derive(Resource, Default)]
+pub struct EditorPalette {
+    pub config_index: usize,
+    pub direction: Direction,
+}
+
+#[derive(Component)]
+pub struct EditorCursor;
+
+#[derive(Component)]
+pub struct EditorHud;
+
+pub struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EditorPalette>()
+            .add_systems(Startup, setup_editor.before(setup_presentation))
+            .add_systems(
+                Startup,
+                spawn_editor_ui
+                    .after(setup_presentation)
+                    .run_if(resource_exists::<EditorMap>),
+            )
+            .add_systems(
+                Update,
+                (
+                    select_palette_obj,
+                    edit_map,
+                    save_edited_map,
+                    update_editor_cursor,
+                    update_editor_hud,
+                )
+                    .chain()
+                    .run_if(resource_exists::<EditorMap>),
+            );
+    }
+}
+
+impl EditorMap {
+    fn get_save_config(
+        &self,
+        game_lib: &GameLib,
+        game_obj_lib: &GameObjInfoLib,
+    ) -> Result<GameMapConfig, MyError> {
+        let mut map_config = self.map_config.clone();
+        for (entity, obj_config) in self.objs.iter() {
+            let mut obj_config = obj_config.clone();
+            if let Some(obj) = entity.and_then(|entity| game_obj_lib.get(&entity)) {
+                obj_config.pos = obj.pos.to_array();
+                obj_config.direction = obj.direction.into();
+            }
+            map_config.objs.push(obj_config);
+        }
+
+        if self.has_grid {
+            map_config.collapse_to_grid(game_lib.get_game_config())?;
+        }
+
+        Ok(map_config)
+    }
+
+    // Player tanks without an id all count as player 0, like in the game
+    fn get_free_player_id(&self, game_lib: &GameLib) -> usize {
+        let used_ids: HashSet<usize> = self
+            .objs
+            .iter()
+            .filter(|(_, obj_config)| is_player_tank(&obj_config.config_name, game_lib))
+            .map(|(_, obj_config)| obj_config.player_id.unwrap_or_default())
+            .collect();
+
+        (0..).find(|id| !used_ids.contains(id)).unwrap_or_default()
+    }
+}
+
+pub fn setup_editor(args: Res<Args>, mut commands: Commands, mut exit_app: MessageWriter<AppExit>) {
+    let (Some(config_path), Some(map_path)) = (args.config_path.as_ref(), args.map_path.as_ref())
+    else {
+        error!("Editing a map needs both a config and a map path");
+        exit_app.write(AppExit::error());
+        return;
+    };
+
+    let Some(game_lib) = load_game_lib(config_path, &mut exit_app) else {
+        return;
+    };
+    let game_config = game_lib.get_game_config();
+
+    let mut map_config = match GameMapConfig::read_raw(map_path, game_config) {
+        Ok(c) => c,
+        Err(err) => {
+            error!("Failed to load map from {:?}: {}", map_path, err);
+            exit_app.write(AppExit::error());
+            return;
+        }
+    };
+    let has_grid = map_config.grid.is_some();
+    if let Err(err) = map_config.expand_grid(game_config) {
+        error!("Failed to load map from {:?}: {}", map_path, err);
+        exit_app.write(AppExit::error());
+        return;
+    }
+
+    let mut map = GameMap::new(
+        game_config.map_cell_size,
+        game_config.map_row_count(),
+        game_config.map_col_count(),
+    );
+    let mut game_obj_lib = GameObjInfoLib(EntityHashMap::new());
+    let mut objs = Vec::new();
+
+    for obj_config in std::mem::take(&mut map_config.objs) {
+        let config_index = game_lib.get_obj_config_index(&obj_config.config_name);
+        let entity = match config_index {
+            Some(config_index) if obj_config.respawn_elapsed.is_none() => map.add_obj(
+                config_index,
+                &arr_to_vec2(&obj_config.pos),
+                &obj_config.direction.into(),
+                &game_lib,
+                &mut game_obj_lib,
+                &mut commands,
+            ),
+            _ => None,
+        };
+
+        objs.push((entity, obj_config));
+    }
+
+    // Tiled maps are saved next to the original as the game's own format
+    let save_path = if map_path.extension().is_some_and(|ext| ext == "json") {
+        map_path.clone()
+    } else {
+        map_path.with_extension("json")
+    };
+
+    commands.insert_resource(EditorMap {
+        save_path,
+        map_config,
+        has_grid,
+        objs,
+    });
+    insert_game_world(map, game_obj_lib, &game_lib, &mut commands);
+    commands.insert_resource(game_lib);
+
+    info!("Editing map {:?}", map_path);
+}
+
+pub fn spawn_editor_ui(mut commands: Commands) {
+    commands.spawn((
+        EditorCursor,
+        Sprite::default(),
+        Transform::default(),
+        Visibility::Hidden,
+    ));
+
+    commands.spawn((
+        EditorHud,
+        Text::new(""),
+        TextFont {
+            font_size: 16.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(8.0),
+            left: Val::Px(8.0),
+            ..default()
+        },
+    ));
+}
+
+pub fn select_palette_obj(
+    keys: Res<ButtonInput<KeyCode>>,
+    mouse_scroll: Res<AccumulatedMouseScroll>,
+    game_lib: Res<GameLib>,
+    mut palette: ResMut<EditorPalette>,
+) {
+    let config_count = game_lib.get_game_config().game_obj_configs.len();
+    let shift_pressed = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+
+    let step = if keys.just_pressed(KeyCode::Tab) {
+        if shift_pressed { -1 } else { 1 }
+    } else if mouse_scroll.delta.y > 0.0 {
+        -1
+    } else if mouse_scroll.delta.y < 0.0 {
+        1
+    } else {
+        return;
+    };
+
+    palette.config_index =
+        (palette.config_index as i32 + step).rem_euclid(config_count as i32) as usize;
+}
+
+// The map being edited, both as shown and as it will be saved
+#[derive(SystemParam)]
+pub struct EditedMapParams<'w, 's> {
+    commands: Commands<'w, 's>,
+    game_lib: Res<'w, GameLib>,
+    editor_map: ResMut<'w, EditorMap>,
+    map: ResMut<'w, GameMap>,
+    game_obj_lib: ResMut<'w, GameObjInfoLib>,
+}
+
+pub fn edit_map(
+    keys: Res<ButtonInput<KeyCode>>,
+    mouse_buttons: Res<ButtonInput<MouseButton>>,
+    game_assets: Res<GameAssets>,
+    mut palette: ResMut<EditorPalette>,
+    window: Single<&Window>,
+    camera: Single<(&Camera, &GlobalTransform)>,
+    edited_map: EditedMapParams,
+) {
+    let EditedMapParams {
+        mut commands,
+        game_lib,
+        mut editor_map,
+        mut map,
+        mut game_obj_lib,
+    } = edited_map;
+    let Some(map_pos) = get_cursor_map_pos(&window, &camera, &game_assets, &map) else {
+        return;
+    };
+    let cell_objs = get_cell_objs(&map_pos, &map, &game_obj_lib);
+
+    if keys.just_pressed(KeyCode::KeyR) {
+        if cell_objs.is_empty() {
+            palette.direction = palette.direction.rotate_clockwise();
+        }
+        for entity in cell_objs.iter() {
+            if let Some(obj) = game_obj_lib.get_mut(entity) {
+                let direction = Direction::from(obj.direction).rotate_clockwise();
+                obj.direction = direction.into();
+            }
+        }
+        return;
+    }
+
+    if mouse_buttons.pressed(MouseButton::Right) {
+        for entity in cell_objs.iter() {
+            remove_edited_obj(
+                entity,
+                &mut map,
+                &mut game_obj_lib,
+                &mut editor_map,
+                &mut commands,
+            );
+        }
+        return;
+    }
+
+    if !mouse_buttons.pressed(MouseButton::Left) {
+        return;
+    }
+
+    // Painting over a cell that already holds the same object does nothing
+    let direction: Vec2 = palette.direction.into();
+    let is_same = |entity: &Entity| {
+        game_obj_lib.get(entity).is_some_and(|obj| {
+            obj.config_index == palette.config_index && obj.direction == direction
+        })
+    };
+    if cell_objs.len() == 1 && is_same(&cell_objs[0]) {
+        return;
+    }
+
+    let obj_config = game_lib.get_obj_config(palette.config_index);
+    let pos = get_cell_center(map_pos.row, map_pos.col, map.cell_size);
+    if !map.is_inside(&pos, obj_config.collide_span) {
+        return;
+    }
+
+    // Solid objects may not overlap the ones left around them, the same rule
+    // the map validator applies
+    let replaced = DespawnPool(cell_objs.iter().copied().collect());
+    if obj_config.obj_type.is_solid()
+        && obj_config.terrain.blocks_tanks()
+        && !map.is_area_free(&pos, obj_config.collide_span, &game_obj_lib, &replaced)
+    {
+        return;
+    }
+
+    for entity in cell_objs.iter() {
+        remove_edited_obj(
+            entity,
+            &mut map,
+            &mut game_obj_lib,
+            &mut editor_map,
+            &mut commands,
+        );
+    }
+
+    let Some(entity) = map.add_obj(
+        palette.config_index,
+        &pos,
+        &direction,
+        game_lib.as_ref(),
+        game_obj_lib.as_mut(),
+        &mut commands,
+    ) else {
+        return;
+    };
+
+    let player_id = is_player_tank(&obj_config.name, &game_lib)
+        .then(|| editor_map.get_free_player_id(&game_lib));
+    editor_map.objs.push((
+        Some(entity),
+        GameMapObjConfig {
+            player_id,
+            ..GameMapObjConfig::new(obj_config.name.clone(), pos.to_array(), palette.direction)
+        },
+    ));
+}
+
+pub fn save_edited_map(
+    keys: Res<ButtonInput<KeyCode>>,
+    game_lib: Res<GameLib>,
+    game_obj_lib: Res<GameObjInfoLib>,
+    editor_map: Res<EditorMap>,
+) {
+    let ctrl_pressed = keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    if !ctrl_pressed || !keys.just_pressed(KeyCode::KeyS) {
+        return;
+    }
+
+    let result = editor_map
+        .get_save_config(game_lib.as_ref(), game_obj_lib.as_ref())
+        .and_then(|map_config| save_game(&editor_map.save_path, &map_config));
+
+    match result {
+        Ok(()) => info!("Saved map to {:?}", editor_map.save_path),
+        Err(err) => error!("Failed to save map to {:?}: {}", editor_map.save_path, err),
+    }
+}
+
+pub fn update_editor_cursor(
+    game_lib: Res<GameLib>,
+    game_assets: Res<GameAssets>,
+    palette: Res<EditorPalette>,
+    map: Res<GameMap>,
+    window: Single<&Window>,
+    camera: Single<(&Camera, &GlobalTransform)>,
+    mut cursor: Single<(&mut Sprite, &mut Transform, &mut Visibility), With<EditorCursor>>,
+) {
+    let (sprite, transform, visibility) = &mut *cursor;
+    if palette.is_changed() {
+        let obj_config = game_lib.get_obj_config(palette.config_index);
+        if let Some(image) = game_assets.get_image(obj_config.get_image(None)) {
+            sprite.image = image;
+        }
+        sprite.custom_size = Some(arr_to_vec2(&obj_config.size));
+        sprite.color.set_alpha(0.5);
+    }
+
+    let Some(map_pos) = get_cursor_map_pos(&window, &camera, &game_assets, &map) else {
+        **visibility = Visibility::Hidden;
+        return;
+    };
+
+    let screen_pos =
+        game_assets.get_screen_pos(&get_cell_center(map_pos.row, map_pos.col, map.cell_size));
+    transform.translation = Vec3::new(screen_pos.x, screen_pos.y, 10.0);
+    transform.rotation = get_rotation(&palette.direction.into());
+    **visibility = Visibility::Visible;
+}
+
+pub fn update_editor_hud(
+    game_lib: Res<GameLib>,
+    palette: Res<EditorPalette>,
+    mut hud: Single<&mut Text, With<EditorHud>>,
+) {
+    if !palette.is_changed() {
+        return;
+    }
+
+    let obj_config = game_lib.get_obj_config(palette.config_index);
+    hud.0 = format!(
+        "{} facing {:?}\n{}",
+        obj_config.name, palette.direction, HELP_TEXT
+    );
+}
+
+fn remove_edited_obj(
+    entity: &Entity,
+    map: &mut GameMap,
+    game_obj_lib: &mut GameObjInfoLib,
+    editor_map: &mut EditorMap,
+    commands: &mut Commands,
+) {
+    if let Some(obj) = game_obj_lib.remove(entity) {
+        map.remove_obj(&obj.map_pos, entity);
+    }
+    editor_map
+        .objs
+        .retain(|(obj_entity, _)| *obj_entity != Some(*entity));
+    commands.entity(*entity).despawn();
+}
+
+fn is_player_tank(config_name: &String, game_lib: &GameLib) -> bool {
+    game_lib
+        .get_obj_config_index(config_name)
+        .map(|config_index| game_lib.get_obj_config(config_index))
+        .is_some_and(|obj_config| {
+            obj_config.obj_type == GameObjType::Tank && obj_config.ai_config.is_none()
+        })
+}
+
+// Objects whose body covers any part of the cell, not just those centered in it
+fn get_cell_objs(map_pos: &MapPos, map: &GameMap, game_obj_lib: &GameObjInfoLib) -> Vec<Entity> {
+    let cell_pos = get_cell_center(map_pos.row, map_pos.col, map.cell_size);
+    let cell_span = map.cell_size / 2.0;
+    let (start_map_pos, end_map_pos) = map.get_collide_region_pass(&cell_pos, cell_span);
+
+    let mut objs = Vec::new();
+    for row in start_map_pos.row..=end_map_pos.row {
+        for col in start_map_pos.col..=end_map_pos.col {
+            objs.extend(map.map[row][col].iter().copied().filter(|entity| {
+                game_obj_lib.get(entity).is_some_and(|obj| {
+                    check_collide_obj_pass(&cell_pos, cell_span, &obj.pos, obj.collide_span)
+                })
+            }));
+        }
+    }
+
+    objs
+}
+
+fn get_cursor_map_pos(
+    window: &Window,
+    (camera, camera_transform): &(&Camera, &GlobalTransform),
+    game_assets: &GameAssets,
+    map: &GameMap,
+) -> Option<MapPos> {
+    let cursor = window.cursor_position()?;
+    let world_pos = camera.viewport_to_world_2d(camera_transform, cursor).ok()?;
+    let pos = world_pos - game_assets.get_screen_pos(&Vec2::ZERO);
+    if pos.x < 0.0 || pos.y < 0.0 || pos.x >= map.width || pos.y >= map.height {
+        return None;
+    }
+
+    Some(map.get_map_pos(&pos))
+}
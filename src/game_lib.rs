@@ -1,4 +1,5 @@
 use crate::ai::*;
+use crate::input::*;
 use crate::my_error::*;
 use crate::utils::*;
 
@@ -14,9 +15,23 @@ pub struct GameConfig {
     image_files: HashMap<String, String>,
     pub game_obj_configs: Vec<GameObjConfig>,
     pub phasing_duration: f32,
+    #[serde(default = "default_player_lives")]
+    pub player_lives: u32,
+    #[serde(default = "default_respawn_delay")]
+    pub respawn_delay: f32,
+    #[serde(default = "default_invulnerable_duration")]
+    pub invulnerable_duration: f32,
     pub explosion_configs: HashMap<String, ExplosionConfig>,
     pub shoot_configs: HashMap<String, ShootConfig>,
     pub ai_configs: Vec<AIConfig>,
+    #[serde(default = "default_teams")]
+    pub teams: Vec<TeamConfig>,
+    #[serde(default = "default_versus_teams")]
+    pub versus_teams: Vec<String>,
+    #[serde(default)]
+    pub key_bindings: KeyBindings,
+    #[serde(default)]
+    pub gamepad_bindings: GamepadBindings,
 }
 
 #[derive(Debug, Resource, Deserialize)]
@@ -26,13 +41,26 @@ pub struct GameObjConfig {
     pub size: [f32; 2],
     pub z: f32,
     pub obj_type: GameObjType,
-    pub side: GameObjSide,
+    // Configs written before teams existed call it side
+    #[serde(alias = "side")]
+    pub team: String,
     pub speed: f32,
     pub collide_span: f32,
     pub shoot_config: Option<String>,
     pub explosion_name: Option<String>,
     pub max_hp: Option<f32>,
     pub ai_config: Option<String>,
+    #[serde(default)]
+    pub damaged_images: Vec<DamagedImageConfig>,
+    #[serde(default)]
+    pub terrain: Terrain,
+}
+
+// Shown once hp drops to `hp` or below
+#[derive(Debug, Deserialize)]
+pub struct DamagedImageConfig {
+    pub hp: f32,
+    pub image: String,
 }
 
 #[derive(Debug, Resource, Deserialize, PartialEq, Eq, Copy, Clone)]
@@ -41,15 +69,112 @@ pub enum GameObjType {
     Tank,
     Missile,
     Effect,
+    Base,
 }
 
-#[derive(Debug, Resource, Deserialize, PartialEq, Eq, Copy, Clone)]
-pub enum GameObjSide {
-    Player,
-    AI,
+impl GameObjType {
+    // Solid objects block tanks, missiles and line of sight
+    #[inline]
+    pub fn is_solid(&self) -> bool {
+        matches!(
+            self,
+            GameObjType::Tile | GameObjType::Tank | GameObjType::Base
+        )
+    }
+
+    // Stationary objects that AI paths go around
+    #[inline]
+    pub fn is_obstacle(&self) -> bool {
+        matches!(self, GameObjType::Tile | GameObjType::Base)
+    }
+}
+
+// Water blocks tanks but not missiles, brush hides the tanks under it and ice
+// keeps them sliding. Only tiles may set a terrain.
+#[derive(Debug, Deserialize, PartialEq, Eq, Copy, Clone, Default)]
+pub enum Terrain {
+    #[default]
+    Solid,
+    Water,
+    Brush,
+    Ice,
+}
+
+impl Terrain {
+    #[inline]
+    pub fn blocks_tanks(&self) -> bool {
+        matches!(self, Terrain::Solid | Terrain::Water)
+    }
+
+    #[inline]
+    pub fn blocks_missiles(&self) -> bool {
+        *self == Terrain::Solid
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Eq, Copy, Clone)]
+pub enum Relation {
+    Ally,
+    Enemy,
     Neutral,
 }
 
+// Relations not listed on either team default to Neutral; a team is always
+// its own ally
+#[derive(Debug, Deserialize)]
+pub struct TeamConfig {
+    pub name: String,
+    #[serde(default)]
+    pub relations: HashMap<String, Relation>,
+}
+
+// A single life, as before players could respawn
+fn default_player_lives() -> u32 {
+    1
+}
+
+fn default_respawn_delay() -> f32 {
+    2.0
+}
+
+fn default_invulnerable_duration() -> f32 {
+    2.0
+}
+
+// The fixed sides used before teams were configurable, plus a rival team for
+// the second player in versus
+fn default_teams() -> Vec<TeamConfig> {
+    let enemies = |names: &[&str]| {
+        names
+            .iter()
+            .map(|name| (name.to_string(), Relation::Enemy))
+            .collect()
+    };
+
+    vec![
+        TeamConfig {
+            name: "Player".to_string(),
+            relations: enemies(&["AI", "Rival"]),
+        },
+        TeamConfig {
+            name: "Rival".to_string(),
+            relations: enemies(&["AI"]),
+        },
+        TeamConfig {
+            name: "AI".to_string(),
+            relations: HashMap::new(),
+        },
+        TeamConfig {
+            name: "Neutral".to_string(),
+            relations: HashMap::new(),
+        },
+    ]
+}
+
+fn default_versus_teams() -> Vec<String> {
+    vec!["Player".to_string(), "Rival".to_string()]
+}
+
 #[derive(Debug, Resource, Deserialize)]
 pub struct ShootConfig {
     pub missile: String,
@@ -71,11 +196,26 @@ pub struct ExplosionConfig {
 #[derive(Debug, Resource)]
 pub struct GameLib {
     config: GameConfig,
-    origin: Vec2,
-    images: HashMap<String, Handle<Image>>,
     game_obj_config_map: HashMap<String, usize>,
-    texture_atlas_layout_map: HashMap<String, Handle<TextureAtlasLayout>>,
     ai_config_map: HashMap<String, usize>,
+    team_map: HashMap<String, usize>,
+    obj_teams: Vec<usize>,
+    relations: Vec<Vec<Relation>>,
+}
+
+impl GameObjConfig {
+    // Picks the most damaged image whose threshold the hp has reached
+    pub fn get_image(&self, hp: Option<f32>) -> &String {
+        let Some(hp) = hp else {
+            return &self.image;
+        };
+
+        self.damaged_images
+            .iter()
+            .filter(|damaged_image| hp <= damaged_image.hp)
+            .min_by(|a, b| a.hp.total_cmp(&b.hp))
+            .map_or(&self.image, |damaged_image| &damaged_image.image)
+    }
 }
 
 impl GameConfig {
@@ -94,28 +234,35 @@ impl GameConfig {
     pub fn window_height(&self) -> f32 {
         self.map_row_count() as f32 * self.map_cell_size
     }
+
+    #[inline]
+    pub fn image_files(&self) -> &HashMap<String, String> {
+        &self.image_files
+    }
 }
 
 impl GameLib {
-    pub fn new<P: AsRef<Path>>(
-        config_path: P,
-        asset_server: &AssetServer,
-        texture_atlas_layouts: &mut Assets<TextureAtlasLayout>,
-    ) -> Result<Self, MyError> {
-        let config: GameConfig = read_json(config_path)?;
-        let origin = Vec2::new(-config.window_width() / 2.0, -config.window_height() / 2.0);
+    pub fn new<P: AsRef<Path>>(config_path: P) -> Result<Self, MyError> {
+        Self::from_config(read_json(config_path)?)
+    }
+
+    pub fn from_config(config: GameConfig) -> Result<Self, MyError> {
+        config.key_bindings.validate()?;
+        config.gamepad_bindings.validate()?;
 
         let mut game_lib = Self {
             config,
-            origin,
-            images: HashMap::new(),
             game_obj_config_map: HashMap::new(),
-            texture_atlas_layout_map: HashMap::new(),
             ai_config_map: HashMap::new(),
+            team_map: HashMap::new(),
+            obj_teams: Vec::new(),
+            relations: Vec::new(),
         };
 
-        game_lib.load_images(asset_server);
-        game_lib.load_configs(texture_atlas_layouts);
+        game_lib.load_configs();
+        game_lib.load_teams()?;
+        game_lib.validate_damaged_images()?;
+        game_lib.validate_terrains()?;
 
         info!("GameLib initialized successfully");
 
@@ -127,11 +274,6 @@ impl GameLib {
         &self.config
     }
 
-    #[inline]
-    pub fn get_screen_pos(&self, pos: &Vec2) -> Vec2 {
-        self.origin + pos
-    }
-
     #[inline]
     pub fn get_obj_config(&self, config_index: usize) -> &GameObjConfig {
         &self.config.game_obj_configs[config_index]
@@ -142,15 +284,14 @@ impl GameLib {
         self.game_obj_config_map.get(name).copied()
     }
 
-    #[inline]
-    pub fn get_image(&self, name: &String) -> Option<Handle<Image>> {
-        match self.images.get(name) {
-            Some(image) => Some(image.clone()),
-            None => {
-                error!("Failed to find image {}", name);
-                None
-            }
-        }
+    // The largest body the nav grid has to fit
+    pub fn get_max_tank_collide_span(&self) -> f32 {
+        self.config
+            .game_obj_configs
+            .iter()
+            .filter(|obj_config| obj_config.obj_type == GameObjType::Tank)
+            .map(|obj_config| obj_config.collide_span)
+            .fold(0.0, f32::max)
     }
 
     #[inline]
@@ -164,58 +305,240 @@ impl GameLib {
     }
 
     #[inline]
-    pub fn get_texture_atlas_layout(
-        &self,
-        explosion_name: &String,
-    ) -> Option<Handle<TextureAtlasLayout>> {
-        match self.texture_atlas_layout_map.get(explosion_name) {
-            Some(layout) => Some(layout.clone()),
-            None => {
-                error!("Failed to find TextureAtlasLayout {}", explosion_name);
-                None
-            }
-        }
+    pub fn get_ai_config(&self, name: &String) -> Option<&AIConfig> {
+        self.ai_config_map
+            .get(name)
+            .map(|index| &self.config.ai_configs[*index])
     }
 
-    fn load_images(&mut self, asset_server: &AssetServer) {
-        for (name, file_path) in self.config.image_files.iter() {
-            self.images
-                .insert(name.clone(), asset_server.load(file_path));
-        }
+    #[inline]
+    pub fn get_team_index(&self, name: &String) -> Option<usize> {
+        self.team_map.get(name).copied()
+    }
+
+    #[inline]
+    pub fn get_team_name(&self, team: usize) -> &String {
+        &self.config.teams[team].name
+    }
+
+    #[inline]
+    pub fn get_obj_team(&self, config_index: usize) -> usize {
+        self.obj_teams[config_index]
+    }
+
+    #[inline]
+    pub fn get_relation(&self, team: usize, other_team: usize) -> Relation {
+        self.relations[team][other_team]
     }
 
-    fn load_configs(&mut self, texture_atlas_layouts: &mut Assets<TextureAtlasLayout>) {
+    fn load_configs(&mut self) {
         for i in 0..self.config.game_obj_configs.len() {
             self.game_obj_config_map
                 .insert(self.get_obj_config(i).name.clone(), i);
+        }
+
+        for (i, ai_config) in self.config.ai_configs.iter().enumerate() {
+            self.ai_config_map.insert(ai_config.name.clone(), i);
+        }
+    }
+
+    fn load_teams(&mut self) -> Result<(), MyError> {
+        let teams = &self.config.teams;
+
+        for (i, team) in teams.iter().enumerate() {
+            if self.team_map.insert(team.name.clone(), i).is_some() {
+                return Err(MyError::Config(format!("Team {} is duplicated", team.name)));
+            }
+        }
+
+        let mut relations = vec![vec![Relation::Neutral; teams.len()]; teams.len()];
+        for (i, team) in teams.iter().enumerate() {
+            relations[i][i] = Relation::Ally;
+
+            for (other_name, relation) in team.relations.iter() {
+                let Some(j) = self.get_team_index(other_name) else {
+                    return Err(MyError::Config(format!(
+                        "Team {} has a relation with unknown team {}",
+                        team.name, other_name
+                    )));
+                };
+
+                if i == j {
+                    return Err(MyError::Config(format!(
+                        "Team {} has a relation with itself",
+                        team.name
+                    )));
+                }
 
-            if let Some(explosion_name) = self.get_obj_config(i).explosion_name.clone() {
-                self.add_texture_layout(&explosion_name, texture_atlas_layouts);
+                if let Some(other_relation) = teams[j].relations.get(&team.name)
+                    && other_relation != relation
+                {
+                    return Err(MyError::Config(format!(
+                        "Teams {} and {} disagree on their relation",
+                        team.name, other_name
+                    )));
+                }
+
+                relations[i][j] = *relation;
+                relations[j][i] = *relation;
+            }
+        }
+        self.relations = relations;
+
+        for obj_config in self.config.game_obj_configs.iter() {
+            let Some(team) = self.get_team_index(&obj_config.team) else {
+                return Err(MyError::Config(format!(
+                    "{} belongs to unknown team {}",
+                    obj_config.name, obj_config.team
+                )));
+            };
+            self.obj_teams.push(team);
+        }
+
+        for name in self.config.versus_teams.iter() {
+            if self.get_team_index(name).is_none() {
+                return Err(MyError::Config(format!("Unknown versus team {}", name)));
             }
         }
+
+        Ok(())
     }
 
-    fn add_texture_layout(
-        &mut self,
-        explosion_name: &String,
-        texture_atlas_layouts: &mut Assets<TextureAtlasLayout>,
-    ) {
-        let Some(explosion_config) = self.get_explosion_config(explosion_name) else {
-            return;
-        };
+    fn validate_damaged_images(&self) -> Result<(), MyError> {
+        for obj_config in self.config.game_obj_configs.iter() {
+            for damaged_image in obj_config.damaged_images.iter() {
+                if !self.config.image_files.contains_key(&damaged_image.image) {
+                    return Err(MyError::Config(format!(
+                        "{} has unknown damaged image {}",
+                        obj_config.name, damaged_image.image
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate_terrains(&self) -> Result<(), MyError> {
+        for obj_config in self.config.game_obj_configs.iter() {
+            if obj_config.obj_type != GameObjType::Tile && obj_config.terrain != Terrain::Solid {
+                return Err(MyError::Config(format!(
+                    "{} is not a tile but has terrain {:?}",
+                    obj_config.name, obj_config.terrain
+                )));
+            }
+        }
 
-        let tile_size = UVec2 {
-            x: explosion_config.size[0],
-            y: explosion_config.size[1],
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_config() -> GameConfig {
+        read_json(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/assets/game_config.json"
+        ))
+        .unwrap()
+    }
+
+    fn get_error(config: GameConfig) -> String {
+        match GameLib::from_config(config) {
+            Err(MyError::Config(message)) => message,
+            Err(err) => panic!("Expected a config error, got {}", err),
+            Ok(_) => panic!("Expected a config error"),
+        }
+    }
+
+    fn get_relation(game_lib: &GameLib, team: &str, other_team: &str) -> Relation {
+        game_lib.get_relation(
+            game_lib.get_team_index(&team.to_string()).unwrap(),
+            game_lib.get_team_index(&other_team.to_string()).unwrap(),
+        )
+    }
+
+    #[test]
+    fn every_team_is_its_own_ally() {
+        let game_lib = GameLib::from_config(read_config()).unwrap();
+
+        for team in 0..game_lib.get_game_config().teams.len() {
+            assert_eq!(game_lib.get_relation(team, team), Relation::Ally);
+        }
+    }
+
+    #[test]
+    fn relations_are_symmetric() {
+        let game_lib = GameLib::from_config(read_config()).unwrap();
+        let team_count = game_lib.get_game_config().teams.len();
+
+        for team in 0..team_count {
+            for other_team in 0..team_count {
+                assert_eq!(
+                    game_lib.get_relation(team, other_team),
+                    game_lib.get_relation(other_team, team)
+                );
+            }
+        }
+        // Only listed on the player team
+        assert_eq!(get_relation(&game_lib, "Rival", "Player"), Relation::Enemy);
+        assert_eq!(get_relation(&game_lib, "AI", "Neutral"), Relation::Neutral);
+    }
+
+    #[test]
+    fn default_teams_match_shipped_teams() {
+        let config = read_config();
+        let get_names = |teams: &[TeamConfig]| -> Vec<String> {
+            teams.iter().map(|team| team.name.clone()).collect()
         };
-        let layout = texture_atlas_layouts.add(TextureAtlasLayout::from_grid(
-            tile_size,
-            explosion_config.frame_count,
-            1,
-            None,
-            None,
-        ));
-        self.texture_atlas_layout_map
-            .insert(explosion_name.clone(), layout);
+
+        assert_eq!(get_names(&default_teams()), get_names(&config.teams));
+        for (team, shipped_team) in default_teams().iter().zip(config.teams.iter()) {
+            assert_eq!(team.relations, shipped_team.relations);
+        }
+        assert_eq!(default_versus_teams(), config.versus_teams);
+    }
+
+    #[test]
+    fn unknown_team_is_rejected() {
+        let mut config = read_config();
+        config.teams[2]
+            .relations
+            .insert("Ghost".to_string(), Relation::Enemy);
+        assert_eq!(
+            get_error(config),
+            "Team AI has a relation with unknown team Ghost"
+        );
+
+        let mut config = read_config();
+        config.game_obj_configs[0].team = "Ghost".to_string();
+        assert_eq!(
+            get_error(config),
+            "player_tank belongs to unknown team Ghost"
+        );
+
+        let mut config = read_config();
+        config.versus_teams[1] = "Ghost".to_string();
+        assert_eq!(get_error(config), "Unknown versus team Ghost");
+    }
+
+    #[test]
+    fn conflicting_relations_are_rejected() {
+        let mut config = read_config();
+        config.teams[2]
+            .relations
+            .insert("Player".to_string(), Relation::Ally);
+        assert_eq!(
+            get_error(config),
+            "Teams Player and AI disagree on their relation"
+        );
+
+        let mut config = read_config();
+        config.teams[3]
+            .relations
+            .insert("Neutral".to_string(), Relation::Ally);
+        assert_eq!(get_error(config), "Team Neutral has a relation with itself");
     }
 }
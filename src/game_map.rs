@@ -1,36 +1,286 @@
+use crate::ai::*;
 use crate::game_lib::*;
 use crate::game_obj::*;
+use crate::game_state::*;
+use crate::grid_map::*;
 use crate::my_error::*;
+use crate::objective::*;
+use crate::pathfinding::*;
+use crate::simulation::*;
+use crate::spawner::*;
+use crate::tiled::*;
 use crate::utils::*;
+use bevy::ecs::entity::EntityHashSet;
 use bevy::prelude::*;
-use serde::Deserialize;
-use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
 use std::path::Path;
+use std::time::Duration;
 
-#[derive(Component, Clone, Copy, Eq, PartialEq)]
+#[derive(Component, Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
 pub struct MapPos {
     pub row: usize,
     pub col: usize,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct GameMapObjConfig {
     pub config_name: String,
     pub pos: [f32; 2],
     pub direction: Direction,
+    // Lets objectives refer to the object
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub player_id: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hp: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shoot_elapsed: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lives: Option<u32>,
+    // Set on a saved player waiting to respawn, whose tank is not placed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub respawn_elapsed: Option<f32>,
+    // Set on a respawned player that is still invulnerable
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invulnerable_elapsed: Option<f32>,
+    // Index of the spawner that produced the tank, counted over the map's
+    // spawners first and then the spawners of each wave
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spawner: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ai: Option<GameMapAIConfig>,
+    // Set when the map is read, never saved
+    #[serde(skip)]
+    pub origin: Option<GameMapObjOrigin>,
 }
 
-#[derive(Deserialize, Clone, Copy)]
+/// Where an object was defined in the map file, so problems can point at it.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum GameMapObjOrigin {
+    Grid {
+        row: usize,
+        col: usize,
+    },
+    Objs(usize),
+    TiledTile {
+        layer: String,
+        row: usize,
+        col: usize,
+    },
+    TiledObject(u32),
+}
+
+impl fmt::Display for GameMapObjOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Grid { row, col } => write!(f, "grid row {} column {}", row, col),
+            Self::Objs(index) => write!(f, "obj {}", index),
+            Self::TiledTile { layer, row, col } => {
+                write!(f, "tile in layer {} at row {} column {}", layer, row, col)
+            }
+            Self::TiledObject(id) => write!(f, "Tiled object {}", id),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GameMapAIConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<Action>,
+    #[serde(default)]
+    pub collision_happened: bool,
+    pub keep_direction_elapsed: f32,
+    pub move_elapsed: f32,
+    pub shoot_elapsed: f32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub path: Vec<MapPos>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GameMapSpawnerConfig {
+    pub config_name: String,
+    pub pos: [f32; 2],
+    pub direction: Direction,
+    pub count: u32,
+    pub interval: f32,
+    pub max_alive: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spawned: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub elapsed: Option<f32>,
+}
+
+// Victory needs every objective that can be completed to be completed;
+// losing a protected object is a defeat. Maps that only protect objects are
+// won the usual way.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
+pub enum GameMapObjectiveConfig {
+    DestroyAllEnemies,
+    Survive {
+        duration: f32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        elapsed: Option<f32>,
+    },
+    Protect {
+        obj_name: String,
+    },
+    ReachZone {
+        pos: [f32; 2],
+        size: [f32; 2],
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reached: Option<bool>,
+    },
+}
+
+// A wave starts once every spawner of the previous wave has spawned all its
+// tanks and none of them is left alive.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GameMapWaveConfig {
+    pub spawners: Vec<GameMapSpawnerConfig>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SpawnPointConfig {
+    pub name: String,
+    pub pos: [f32; 2],
+    pub direction: Direction,
+    // Reserved for one player, otherwise shared by all
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub player_id: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GameMapExplosionConfig {
+    pub explosion_name: String,
+    pub pos: [f32; 2],
+    pub index: usize,
+    pub elapsed: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GameMapPhasingObjConfig {
+    pub config_name: String,
+    pub pos: [f32; 2],
+    pub direction: Direction,
+    pub elapsed: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub player_id: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
 pub enum Direction {
     Right,
     Left,
+    #[default]
     Up,
     Down,
 }
 
-#[derive(Deserialize)]
+// Saved games use the map format too, with the runtime state of explosions,
+// phasing objects and the simulation appended.
+#[derive(Serialize, Deserialize, Clone, Default)]
 pub struct GameMapConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grid: Option<GameMapGridConfig>,
+    #[serde(default)]
     pub objs: Vec<GameMapObjConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub spawn_points: Vec<SpawnPointConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub spawners: Vec<GameMapSpawnerConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub waves: Vec<GameMapWaveConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current_wave: Option<usize>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub objectives: Vec<GameMapObjectiveConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub explosions: Vec<GameMapExplosionConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub phasing_objs: Vec<GameMapPhasingObjConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sim_tick: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+impl GameMapObjConfig {
+    pub fn new(config_name: String, pos: [f32; 2], direction: Direction) -> Self {
+        Self {
+            config_name,
+            pos,
+            direction,
+            name: None,
+            player_id: None,
+            team: None,
+            hp: None,
+            shoot_elapsed: None,
+            lives: None,
+            respawn_elapsed: None,
+            invulnerable_elapsed: None,
+            spawner: None,
+            ai: None,
+            origin: None,
+        }
+    }
+}
+
+impl GameMapSpawnerConfig {
+    /// Describes why the spawner could never finish or would spawn a tank
+    /// every tick.
+    pub fn get_problem(&self) -> Option<String> {
+        if self.count == 0 {
+            Some("count is 0, so it never spawns".to_string())
+        } else if self.max_alive == 0 {
+            Some("max_alive is 0, so it never finishes".to_string())
+        } else if self.interval.is_nan() || self.interval <= 0.0 {
+            Some(format!("interval {} is not positive", self.interval))
+        } else {
+            None
+        }
+    }
+}
+
+impl GameMapConfig {
+    /// Reads a map in either format, expanding a grid into objects. Maps
+    /// exported from Tiled as JSON (.tmj) are imported as well.
+    pub fn read<P: AsRef<Path>>(map_path: P, game_config: &GameConfig) -> Result<Self, MyError> {
+        let mut map_config = Self::read_raw(map_path, game_config)?;
+        map_config.expand_grid(game_config)?;
+        Ok(map_config)
+    }
+
+    /// Reads a map without expanding its grid.
+    pub fn read_raw<P: AsRef<Path>>(
+        map_path: P,
+        game_config: &GameConfig,
+    ) -> Result<Self, MyError> {
+        let map_path = map_path.as_ref();
+        match map_path.extension().and_then(|ext| ext.to_str()) {
+            Some("tmj") => load_tiled_map(map_path, game_config),
+            Some("tmx") => Err(MyError::Map(
+                "Tiled XML maps (.tmx) are not supported, export the map as JSON (.tmj)"
+                    .to_string(),
+            )),
+            _ => {
+                let mut map_config: Self = read_json(map_path)?;
+                for (index, obj) in map_config.objs.iter_mut().enumerate() {
+                    obj.origin = Some(GameMapObjOrigin::Objs(index));
+                }
+                Ok(map_config)
+            }
+        }
+    }
 }
 
 #[derive(Resource)]
@@ -38,8 +288,9 @@ pub struct GameMap {
     pub cell_size: f32,
     pub width: f32,
     pub height: f32,
-    pub map: Vec<Vec<HashSet<Entity>>>,
+    pub map: Vec<Vec<EntityHashSet>>,
     pub max_collide_span: f32,
+    pub spawn_points: Vec<SpawnPointConfig>,
 }
 
 impl GameMap {
@@ -48,24 +299,46 @@ impl GameMap {
             cell_size,
             width: col_count as f32 * cell_size,
             height: row_count as f32 * cell_size,
-            map: vec![vec![HashSet::new(); col_count]; row_count],
+            map: vec![vec![EntityHashSet::new(); col_count]; row_count],
             max_collide_span: 0.0,
+            spawn_points: Vec::new(),
         }
     }
 
     pub fn load<P: AsRef<Path>>(
         map_path: P,
         game_lib: &GameLib,
+        mode: GameMode,
         game_obj_lib: &mut GameObjInfoLib,
         commands: &mut Commands,
     ) -> Result<GameMap, MyError> {
-        let map_config: GameMapConfig = read_json(map_path.as_ref())?;
+        let map_config = GameMapConfig::read(map_path, game_lib.get_game_config())?;
+        Ok(Self::from_config(
+            &map_config,
+            game_lib,
+            mode,
+            game_obj_lib,
+            commands,
+        ))
+    }
+
+    pub fn from_config(
+        map_config: &GameMapConfig,
+        game_lib: &GameLib,
+        mode: GameMode,
+        game_obj_lib: &mut GameObjInfoLib,
+        commands: &mut Commands,
+    ) -> GameMap {
         let game_config = &game_lib.get_game_config();
         let mut map = GameMap::new(
             game_config.map_cell_size,
             game_config.map_row_count(),
             game_config.map_col_count(),
         );
+        let mut player_info = PlayerInfo::default();
+        let mut spawner_info = SpawnerInfo::from_config(map_config, game_lib);
+        let mut obj_names: HashMap<String, Entity> = HashMap::new();
+        map.spawn_points = map_config.spawn_points.clone();
 
         for map_obj_config in map_config.objs.iter() {
             let Some(config_index) = game_lib.get_obj_config_index(&map_obj_config.config_name)
@@ -79,17 +352,251 @@ impl GameMap {
             let pos = arr_to_vec2(&map_obj_config.pos);
             let direction: Vec2 = map_obj_config.direction.into();
 
-            map.add_obj(
+            if let Some(elapsed) = map_obj_config.respawn_elapsed {
+                let Some(mut slot) = Self::create_player_slot(
+                    map_obj_config,
+                    config_index,
+                    Entity::PLACEHOLDER,
+                    game_lib,
+                    mode,
+                ) else {
+                    continue;
+                };
+                let mut respawn_timer =
+                    Timer::from_seconds(game_config.respawn_delay, TimerMode::Once);
+                respawn_timer.set_elapsed(Duration::from_secs_f32(elapsed));
+                slot.respawn_timer = Some(respawn_timer);
+                Self::insert_player_slot(map_obj_config, slot, &mut player_info);
+                continue;
+            }
+
+            let Some(entity) = map.add_obj(
                 config_index,
                 &pos,
                 &direction,
                 game_lib,
                 game_obj_lib,
                 commands,
+            ) else {
+                continue;
+            };
+
+            let Some(obj) = game_obj_lib.get_mut(&entity) else {
+                continue;
+            };
+
+            if let Some(hp) = map_obj_config.hp {
+                obj.hp = Some(hp);
+            }
+
+            if let Some(name) = map_obj_config.name.as_ref() {
+                if obj_names.insert(name.clone(), entity).is_some() {
+                    warn!("Object name {} is used more than once", name);
+                }
+                commands.entity(entity).insert(Name::new(name.clone()));
+            }
+
+            if let Some(index) = map_obj_config.spawner {
+                match spawner_info.spawners.get_mut(index) {
+                    Some(spawner) => {
+                        spawner.alive.insert(entity);
+                    }
+                    None => warn!("Failed to find spawner {}", index),
+                }
+            }
+
+            // Tanks without an AI config are driven by a player
+            if let Some(slot) =
+                Self::create_player_slot(map_obj_config, config_index, entity, game_lib, mode)
+            {
+                obj.team = slot.team;
+                commands.entity(entity).insert(PlayerComponent {
+                    player_id: map_obj_config.player_id.unwrap_or_default(),
+                });
+                Self::insert_player_slot(map_obj_config, slot, &mut player_info);
+            } else if let Some(team_name) = map_obj_config.team.as_ref() {
+                match game_lib.get_team_index(team_name) {
+                    Some(team) => obj.team = team,
+                    None => warn!("Failed to find team {} in GameLib", team_name),
+                }
+            }
+
+            if let Some(elapsed) = map_obj_config.shoot_elapsed {
+                commands
+                    .entity(entity)
+                    .entry::<ShootComponent>()
+                    .and_modify(move |mut shoot_comp| {
+                        shoot_comp
+                            .timer
+                            .set_elapsed(Duration::from_secs_f32(elapsed));
+                    });
+            }
+
+            if let Some(elapsed) = map_obj_config.invulnerable_elapsed {
+                obj.invulnerable = true;
+                let mut timer =
+                    Timer::from_seconds(game_config.invulnerable_duration, TimerMode::Once);
+                timer.set_elapsed(Duration::from_secs_f32(elapsed));
+                commands.entity(entity).insert(InvulnerableComponent(timer));
+            }
+
+            if let Some(ai_config) = map_obj_config.ai.clone() {
+                commands
+                    .entity(entity)
+                    .entry::<AIComponent>()
+                    .and_modify(move |mut ai_comp| ai_comp.load_save_config(&ai_config));
+            }
+        }
+
+        Self::load_explosions(&map_config.explosions, game_lib, commands);
+        Self::load_phasing_objs(
+            &map_config.phasing_objs,
+            game_lib,
+            &mut player_info,
+            commands,
+        );
+        commands.insert_resource(player_info);
+        commands.insert_resource(spawner_info);
+        commands.insert_resource(ObjectiveInfo::from_config(
+            &map_config.objectives,
+            &obj_names,
+        ));
+        if let Some(sim_tick) = map_config.sim_tick {
+            commands.insert_resource(SimTick(sim_tick));
+        }
+        if let Some(seed) = map_config.seed {
+            commands.insert_resource(GameRng::new(seed));
+        }
+
+        map
+    }
+
+    fn create_player_slot(
+        map_obj_config: &GameMapObjConfig,
+        config_index: usize,
+        entity: Entity,
+        game_lib: &GameLib,
+        mode: GameMode,
+    ) -> Option<PlayerSlot> {
+        let obj_config = game_lib.get_obj_config(config_index);
+        if obj_config.obj_type != GameObjType::Tank || obj_config.ai_config.is_some() {
+            return None;
+        }
+
+        let player_id = map_obj_config.player_id.unwrap_or_default();
+        let mut team = game_lib.get_obj_team(config_index);
+        if let Some(player_team) = mode.get_player_team(player_id, game_lib) {
+            team = player_team;
+        }
+        if let Some(team_name) = map_obj_config.team.as_ref() {
+            match game_lib.get_team_index(team_name) {
+                Some(t) => team = t,
+                None => warn!("Failed to find team {} in GameLib", team_name),
+            }
+        }
+
+        Some(PlayerSlot {
+            entity,
+            config_index,
+            team,
+            spawn_pos: arr_to_vec2(&map_obj_config.pos),
+            spawn_direction: map_obj_config.direction.into(),
+            lives: map_obj_config
+                .lives
+                .unwrap_or(game_lib.get_game_config().player_lives),
+            respawn_timer: None,
+        })
+    }
+
+    fn insert_player_slot(
+        map_obj_config: &GameMapObjConfig,
+        slot: PlayerSlot,
+        player_info: &mut PlayerInfo,
+    ) {
+        let player_id = map_obj_config.player_id.unwrap_or_default();
+        if player_info.contains_key(&player_id) {
+            warn!("Player {} is placed more than once", player_id);
+        }
+        player_info.insert(player_id, slot);
+    }
+
+    fn load_explosions(
+        explosion_configs: &[GameMapExplosionConfig],
+        game_lib: &GameLib,
+        commands: &mut Commands,
+    ) {
+        for config in explosion_configs.iter() {
+            let Some(explosion_config) = game_lib.get_explosion_config(&config.explosion_name)
+            else {
+                warn!("Failed to find ExplosionConfig {}", config.explosion_name);
+                continue;
+            };
+
+            let mut explosion_comp = ExplosionComponent::new(
+                &arr_to_vec2(&config.pos),
+                &config.explosion_name,
+                explosion_config,
             );
+            explosion_comp.index = config.index.min(explosion_comp.last_index - 1);
+            explosion_comp
+                .timer
+                .set_elapsed(Duration::from_secs_f32(config.elapsed));
+            commands.spawn(explosion_comp);
         }
+    }
 
-        Ok(map)
+    // A dying player has already lost the life, so a player out of lives only
+    // keeps a slot to count in versus until the tank is gone
+    fn load_phasing_objs(
+        phasing_obj_configs: &[GameMapPhasingObjConfig],
+        game_lib: &GameLib,
+        player_info: &mut PlayerInfo,
+        commands: &mut Commands,
+    ) {
+        let phasing_duration = game_lib.get_game_config().phasing_duration;
+
+        for config in phasing_obj_configs.iter() {
+            let Some(config_index) = game_lib.get_obj_config_index(&config.config_name) else {
+                warn!(
+                    "Failed to find config name {} in GameLib",
+                    config.config_name
+                );
+                continue;
+            };
+
+            let mut team = game_lib.get_obj_team(config_index);
+            if let Some(team_name) = config.team.as_ref() {
+                match game_lib.get_team_index(team_name) {
+                    Some(t) => team = t,
+                    None => warn!("Failed to find team {} in GameLib", team_name),
+                }
+            }
+
+            let pos = arr_to_vec2(&config.pos);
+            let direction: Vec2 = config.direction.into();
+            let mut phasing_comp = PhasingComponent::new(phasing_duration, &pos, &direction, team);
+            phasing_comp
+                .timer
+                .set_elapsed(Duration::from_secs_f32(config.elapsed));
+            let entity = commands
+                .spawn((GameObjComponent { config_index }, phasing_comp))
+                .id();
+
+            if let Some(player_id) = config.player_id {
+                commands
+                    .entity(entity)
+                    .insert(PlayerComponent { player_id });
+                player_info.entry(player_id).or_insert(PlayerSlot {
+                    entity: Entity::PLACEHOLDER,
+                    config_index,
+                    team,
+                    spawn_pos: pos,
+                    spawn_direction: direction,
+                    lives: 0,
+                    respawn_timer: None,
+                });
+            }
+        }
     }
 
     pub fn add_obj(
@@ -100,30 +607,111 @@ impl GameMap {
         game_lib: &GameLib,
         game_obj_lib: &mut GameObjInfoLib,
         commands: &mut Commands,
-    ) {
+    ) -> Option<Entity> {
         let obj_config = game_lib.get_obj_config(config_index);
 
-        if !self.is_inside(&pos, obj_config.collide_span) {
+        if !self.is_inside(pos, obj_config.collide_span) {
             error!("Position {:?} is outside map", pos);
-            return;
+            return None;
+        }
+
+        let map_pos = self.get_map_pos(pos);
+
+        let (obj, entity) =
+            GameObjInfo::new(config_index, pos, &map_pos, direction, game_lib, commands);
+
+        self.map[map_pos.row][map_pos.col].insert(entity);
+        if self.max_collide_span < obj_config.collide_span {
+            self.max_collide_span = obj_config.collide_span;
+        }
+
+        game_obj_lib.insert(entity, obj);
+
+        Some(entity)
+    }
+
+    /// Checks that a body of `collide_span` fits at `pos` without overlapping
+    /// any solid object.
+    pub fn is_area_free(
+        &self,
+        pos: &Vec2,
+        collide_span: f32,
+        game_obj_lib: &GameObjInfoLib,
+        despawn_pool: &DespawnPool,
+    ) -> bool {
+        if !self.is_inside(pos, collide_span) {
+            return false;
         }
 
-        let map_pos = self.get_map_pos(&pos);
+        let (start_map_pos, end_map_pos) = self.get_collide_region_pass(pos, collide_span);
+
+        for row in start_map_pos.row..=end_map_pos.row {
+            for col in start_map_pos.col..=end_map_pos.col {
+                for e in self.map[row][col].iter() {
+                    if despawn_pool.contains(e) {
+                        continue;
+                    }
+
+                    let Some(obj) = game_obj_lib.get(e) else {
+                        continue;
+                    };
+
+                    if !obj.obj_type.is_solid()
+                        || !obj.terrain.blocks_tanks()
+                        || obj.collide_span == 0.0
+                    {
+                        continue;
+                    }
 
-        if let Some((obj, entity)) =
-            GameObjInfo::new(config_index, pos, &map_pos, direction, game_lib, commands)
-        {
-            if obj_config.side == GameObjSide::Player {
-                commands.insert_resource(PlayerInfo(Some(entity)));
+                    if check_collide_obj_pass(pos, collide_span, &obj.pos, obj.collide_span) {
+                        return false;
+                    }
+                }
             }
+        }
+
+        true
+    }
+
+    /// Checks whether `pos` lies on a tile of the given terrain.
+    pub fn is_on_terrain(
+        &self,
+        pos: &Vec2,
+        terrain: Terrain,
+        game_obj_lib: &GameObjInfoLib,
+    ) -> bool {
+        let (start_map_pos, end_map_pos) = self.get_collide_region_pass(pos, 0.0);
 
-            self.map[map_pos.row][map_pos.col].insert(entity);
-            if self.max_collide_span < obj_config.collide_span {
-                self.max_collide_span = obj_config.collide_span;
+        for row in start_map_pos.row..=end_map_pos.row {
+            for col in start_map_pos.col..=end_map_pos.col {
+                for e in self.map[row][col].iter() {
+                    let Some(obj) = game_obj_lib.get(e) else {
+                        continue;
+                    };
+
+                    if obj.obj_type == GameObjType::Tile
+                        && obj.terrain == terrain
+                        && check_collide_obj_pass(pos, 0.0, &obj.pos, obj.collide_span)
+                    {
+                        return true;
+                    }
+                }
             }
+        }
+
+        false
+    }
+
+    pub fn build_nav_grid(&self, game_obj_lib: &GameObjInfoLib, game_lib: &GameLib) -> NavGrid {
+        let mut nav_grid = NavGrid::from_map(self, game_lib.get_max_tank_collide_span());
 
-            game_obj_lib.insert(entity, obj);
+        for obj in game_obj_lib.values() {
+            if obj.obj_type.is_obstacle() && obj.terrain.blocks_tanks() && obj.collide_span > 0.0 {
+                nav_grid.add_obstacle(&obj.pos, obj.collide_span);
+            }
         }
+
+        nav_grid
     }
 
     #[inline]
@@ -138,17 +726,22 @@ impl GameMap {
 
     #[inline]
     pub fn is_inside(&self, pos: &Vec2, collide_span: f32) -> bool {
+        // Tanks pushed against the right or top wall sit exactly on the bound
         pos.x >= collide_span
-            && pos.x + collide_span < self.width
+            && pos.x + collide_span <= self.width
+            && pos.x < self.width
             && pos.y >= collide_span
-            && pos.y + collide_span < self.height
+            && pos.y + collide_span <= self.height
+            && pos.y < self.height
     }
 
+    // Missiles leave the map before they are despawned, so positions outside
+    // it map to the nearest border cell
     #[inline]
     pub fn get_map_pos(&self, pos: &Vec2) -> MapPos {
         MapPos {
-            row: (pos.y / self.cell_size).floor() as usize,
-            col: (pos.x / self.cell_size).floor() as usize,
+            row: self.clamp_row(pos.y),
+            col: self.clamp_col(pos.x),
         }
     }
 
@@ -203,6 +796,7 @@ impl GameMap {
         &self,
         entity: &Entity,
         obj: &GameObjInfo,
+        game_lib: &GameLib,
         game_obj_lib: &GameObjInfoLib,
         despawn_pool: &DespawnPool,
         time: &Time,
@@ -213,7 +807,8 @@ impl GameMap {
             return (true, pos);
         }
 
-        let collide = self.check_missile_collide(entity, &pos, obj, game_obj_lib, despawn_pool);
+        let collide =
+            self.check_missile_collide(entity, &pos, obj, game_lib, game_obj_lib, despawn_pool);
 
         (collide, pos)
     }
@@ -228,8 +823,8 @@ impl GameMap {
     ) -> (bool, Vec2) {
         let mut collide = false;
         let (start_map_pos, end_map_pos) =
-            self.get_collide_region_nonpass(&obj.pos, &new_pos, obj.collide_span);
-        let mut pos = new_pos.clone();
+            self.get_collide_region_nonpass(&obj.pos, new_pos, obj.collide_span);
+        let mut pos = *new_pos;
 
         for row in start_map_pos.row..=end_map_pos.row {
             for col in start_map_pos.col..=end_map_pos.col {
@@ -243,7 +838,8 @@ impl GameMap {
                         continue;
                     };
 
-                    if (obj2.obj_type != GameObjType::Tank && obj2.obj_type != GameObjType::Tile)
+                    if !obj2.obj_type.is_solid()
+                        || !obj2.terrain.blocks_tanks()
                         || obj2.collide_span == 0.0
                     {
                         continue;
@@ -274,6 +870,7 @@ impl GameMap {
         entity: &Entity,
         new_pos: &Vec2,
         obj: &GameObjInfo,
+        game_lib: &GameLib,
         game_obj_lib: &GameObjInfoLib,
         despawn_pool: &DespawnPool,
     ) -> bool {
@@ -291,9 +888,10 @@ impl GameMap {
                         continue;
                     };
 
-                    if (obj2.obj_type != GameObjType::Tank && obj2.obj_type != GameObjType::Tile)
+                    if !obj2.obj_type.is_solid()
+                        || !obj2.terrain.blocks_missiles()
                         || obj2.collide_span == 0.0
-                        || obj.side == obj2.side
+                        || game_lib.get_relation(obj.team, obj2.team) == Relation::Ally
                     {
                         continue;
                     }
@@ -313,6 +911,132 @@ impl GameMap {
         false
     }
 
+    /// Finds the first object blocking missiles that a body of `collide_span`
+    /// hits moving from `start` to `end`. Objects for which `passes_through`
+    /// returns true are skipped.
+    pub fn cast_ray(
+        &self,
+        start: &Vec2,
+        end: &Vec2,
+        collide_span: f32,
+        game_obj_lib: &GameObjInfoLib,
+        despawn_pool: &DespawnPool,
+        passes_through: impl Fn(&Entity, &GameObjInfo) -> bool,
+    ) -> Option<Entity> {
+        let span = collide_span + self.max_collide_span;
+        let mut visited = vec![false; self.row_count() * self.col_count()];
+        let mut hit: Option<(Entity, f32)> = None;
+
+        for cell in self.get_segment_cells(start, end) {
+            let (start_map_pos, end_map_pos) = self.get_map_region(
+                cell.col as f32 * self.cell_size - span,
+                cell.row as f32 * self.cell_size - span,
+                (cell.col + 1) as f32 * self.cell_size + span,
+                (cell.row + 1) as f32 * self.cell_size + span,
+            );
+
+            for row in start_map_pos.row..=end_map_pos.row {
+                for col in start_map_pos.col..=end_map_pos.col {
+                    let index = row * self.col_count() + col;
+                    if visited[index] {
+                        continue;
+                    }
+                    visited[index] = true;
+
+                    for e in self.map[row][col].iter() {
+                        if despawn_pool.contains(e) {
+                            continue;
+                        }
+
+                        let Some(obj) = game_obj_lib.get(e) else {
+                            warn!("Cannot find entity {e} in map");
+                            continue;
+                        };
+
+                        if !obj.obj_type.is_solid()
+                            || !obj.terrain.blocks_missiles()
+                            || obj.collide_span == 0.0
+                            || passes_through(e, obj)
+                        {
+                            continue;
+                        }
+
+                        let Some(t) = check_collide_segment_obj(
+                            start,
+                            end,
+                            &obj.pos,
+                            obj.collide_span + collide_span,
+                        ) else {
+                            continue;
+                        };
+
+                        if hit.is_none_or(|(_, best)| t < best) {
+                            hit = Some((*e, t));
+                        }
+                    }
+                }
+            }
+        }
+
+        hit.map(|(e, _)| e)
+    }
+
+    pub fn get_segment_cells(&self, start: &Vec2, end: &Vec2) -> Vec<MapPos> {
+        let delta = end - start;
+        let mut row = self.clamp_row(start.y) as i32;
+        let mut col = self.clamp_col(start.x) as i32;
+        let end_row = self.clamp_row(end.y) as i32;
+        let end_col = self.clamp_col(end.x) as i32;
+
+        let step_col = delta.x.signum() as i32;
+        let step_row = delta.y.signum() as i32;
+        let mut t_max_x = self.get_cell_boundary_t(start.x, delta.x, col);
+        let mut t_max_y = self.get_cell_boundary_t(start.y, delta.y, row);
+        let t_delta_x = (self.cell_size / delta.x).abs();
+        let t_delta_y = (self.cell_size / delta.y).abs();
+        let mut cells = Vec::new();
+
+        loop {
+            cells.push(MapPos {
+                row: row as usize,
+                col: col as usize,
+            });
+
+            if row == end_row && col == end_col {
+                break;
+            }
+
+            if t_max_x < t_max_y {
+                col += step_col;
+                t_max_x += t_delta_x;
+            } else {
+                row += step_row;
+                t_max_y += t_delta_y;
+            }
+
+            if row < 0
+                || row >= self.row_count() as i32
+                || col < 0
+                || col >= self.col_count() as i32
+            {
+                break;
+            }
+        }
+
+        cells
+    }
+
+    #[inline]
+    fn get_cell_boundary_t(&self, s: f32, d: f32, index: i32) -> f32 {
+        if d > 0.0 {
+            ((index + 1) as f32 * self.cell_size - s) / d
+        } else if d < 0.0 {
+            (index as f32 * self.cell_size - s) / d
+        } else {
+            f32::INFINITY
+        }
+    }
+
     #[inline]
     pub fn get_collide_region_nonpass(
         &self,
@@ -357,7 +1081,34 @@ impl GameMap {
     #[inline]
     pub fn relocate(&mut self, entity: &Entity, old_pos: &MapPos, new_pos: &MapPos) {
         self.map[old_pos.row][old_pos.col].remove(entity);
-        self.map[new_pos.row][new_pos.col].insert(entity.clone());
+        self.map[new_pos.row][new_pos.col].insert(*entity);
+    }
+}
+
+impl Direction {
+    pub fn rotate_clockwise(&self) -> Self {
+        match self {
+            Direction::Up => Direction::Right,
+            Direction::Right => Direction::Down,
+            Direction::Down => Direction::Left,
+            Direction::Left => Direction::Up,
+        }
+    }
+}
+
+impl From<Vec2> for Direction {
+    fn from(direction: Vec2) -> Self {
+        if direction.x.abs() >= direction.y.abs() {
+            if direction.x < 0.0 {
+                Direction::Left
+            } else {
+                Direction::Right
+            }
+        } else if direction.y < 0.0 {
+            Direction::Down
+        } else {
+            Direction::Up
+        }
     }
 }
 
@@ -371,3 +1122,293 @@ impl From<Direction> for Vec2 {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::entity::EntityHashMap;
+
+    const CONFIG_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/game_config.json");
+
+    fn cells(positions: &[(usize, usize)]) -> Vec<MapPos> {
+        positions
+            .iter()
+            .map(|(row, col)| MapPos {
+                row: *row,
+                col: *col,
+            })
+            .collect()
+    }
+
+    // Registers an obj on the map without spawning it
+    fn add_obj(
+        map: &mut GameMap,
+        game_obj_lib: &mut GameObjInfoLib,
+        game_lib: &GameLib,
+        config_name: &str,
+        pos: Vec2,
+    ) -> Entity {
+        let config_index = game_lib
+            .get_obj_config_index(&config_name.to_string())
+            .unwrap();
+        let obj_config = game_lib.get_obj_config(config_index);
+        let entity = Entity::from_raw_u32(game_obj_lib.len() as u32 + 1).unwrap();
+        let map_pos = map.get_map_pos(&pos);
+
+        map.map[map_pos.row][map_pos.col].insert(entity);
+        map.max_collide_span = map.max_collide_span.max(obj_config.collide_span);
+        game_obj_lib.insert(
+            entity,
+            GameObjInfo {
+                config_index,
+                pos,
+                map_pos,
+                direction: Vec2::from(Direction::Up),
+                team: game_lib.get_obj_team(config_index),
+                obj_type: obj_config.obj_type,
+                terrain: obj_config.terrain,
+                collide_span: obj_config.collide_span,
+                speed: obj_config.speed,
+                hp: obj_config.max_hp,
+                invulnerable: false,
+            },
+        );
+
+        entity
+    }
+
+    // A 5x5 map with 40 wide cells, holding steel tiles at the given positions
+    fn tile_map(game_lib: &GameLib, tile_positions: &[Vec2]) -> (GameMap, GameObjInfoLib) {
+        let mut map = GameMap::new(40.0, 5, 5);
+        let mut game_obj_lib = GameObjInfoLib(EntityHashMap::default());
+        for pos in tile_positions {
+            add_obj(&mut map, &mut game_obj_lib, game_lib, "steel_tile", *pos);
+        }
+
+        (map, game_obj_lib)
+    }
+
+    fn cast_ray(
+        map: &GameMap,
+        game_obj_lib: &GameObjInfoLib,
+        game_lib: &GameLib,
+        start: Vec2,
+        end: Vec2,
+    ) -> Option<Entity> {
+        let player_tank = game_lib
+            .get_obj_config_index(&"player_tank".to_string())
+            .unwrap();
+        let team = game_lib.get_obj_team(player_tank);
+        map.cast_ray(
+            &start,
+            &end,
+            0.0,
+            game_obj_lib,
+            &DespawnPool(EntityHashSet::default()),
+            |_, obj| game_lib.get_relation(team, obj.team) == Relation::Ally,
+        )
+    }
+
+    #[test]
+    fn map_pos_outside_the_map_is_clamped() {
+        let map = GameMap::new(40.0, 5, 5);
+
+        assert_eq!(
+            map.get_map_pos(&Vec2::new(60.0, 100.0)),
+            MapPos { row: 2, col: 1 }
+        );
+        assert_eq!(
+            map.get_map_pos(&Vec2::new(-30.0, 100.0)),
+            MapPos { row: 2, col: 0 }
+        );
+        assert_eq!(
+            map.get_map_pos(&Vec2::new(60.0, -0.5)),
+            MapPos { row: 0, col: 1 }
+        );
+        assert_eq!(
+            map.get_map_pos(&Vec2::new(200.0, 100.0)),
+            MapPos { row: 2, col: 4 }
+        );
+        assert_eq!(
+            map.get_map_pos(&Vec2::new(60.0, 330.0)),
+            MapPos { row: 4, col: 1 }
+        );
+    }
+
+    #[test]
+    fn segment_cells_along_an_axis() {
+        let map = GameMap::new(40.0, 5, 5);
+
+        let segment_cells = map.get_segment_cells(&Vec2::new(20.0, 20.0), &Vec2::new(180.0, 20.0));
+        assert_eq!(
+            segment_cells,
+            cells(&[(0, 0), (0, 1), (0, 2), (0, 3), (0, 4)])
+        );
+
+        let segment_cells = map.get_segment_cells(&Vec2::new(60.0, 180.0), &Vec2::new(60.0, 60.0));
+        assert_eq!(segment_cells, cells(&[(4, 1), (3, 1), (2, 1), (1, 1)]));
+    }
+
+    // Passing exactly through a cell corner also visits one of the cells
+    // beside it
+    #[test]
+    fn segment_cells_along_a_diagonal() {
+        let map = GameMap::new(40.0, 5, 5);
+
+        let segment_cells = map.get_segment_cells(&Vec2::new(20.0, 20.0), &Vec2::new(100.0, 100.0));
+        assert_eq!(
+            segment_cells,
+            cells(&[(0, 0), (1, 0), (1, 1), (2, 1), (2, 2)])
+        );
+
+        let segment_cells = map.get_segment_cells(&Vec2::new(10.0, 30.0), &Vec2::new(70.0, 50.0));
+        assert_eq!(segment_cells, cells(&[(0, 0), (1, 0), (1, 1)]));
+    }
+
+    #[test]
+    fn segment_cells_ending_on_a_cell_edge() {
+        let map = GameMap::new(40.0, 5, 5);
+
+        let segment_cells = map.get_segment_cells(&Vec2::new(20.0, 20.0), &Vec2::new(80.0, 20.0));
+        assert_eq!(segment_cells, cells(&[(0, 0), (0, 1), (0, 2)]));
+
+        let segment_cells = map.get_segment_cells(&Vec2::new(40.0, 20.0), &Vec2::new(100.0, 20.0));
+        assert_eq!(segment_cells, cells(&[(0, 1), (0, 2)]));
+    }
+
+    #[test]
+    fn zero_length_segment_has_one_cell() {
+        let map = GameMap::new(40.0, 5, 5);
+
+        let segment_cells = map.get_segment_cells(&Vec2::new(60.0, 60.0), &Vec2::new(60.0, 60.0));
+        assert_eq!(segment_cells, cells(&[(1, 1)]));
+    }
+
+    #[test]
+    fn ray_hits_nearest_obj() {
+        let game_lib = GameLib::new(CONFIG_PATH).unwrap();
+        let (map, game_obj_lib) = tile_map(
+            &game_lib,
+            &[
+                Vec2::new(180.0, 20.0),
+                Vec2::new(100.0, 20.0),
+                Vec2::new(100.0, 100.0),
+            ],
+        );
+        let near_tile = map.map[0][2].iter().next().copied();
+        let diagonal_tile = map.map[2][2].iter().next().copied();
+
+        let hit = cast_ray(
+            &map,
+            &game_obj_lib,
+            &game_lib,
+            Vec2::new(20.0, 20.0),
+            Vec2::new(190.0, 20.0),
+        );
+        assert_eq!(hit, near_tile);
+
+        let hit = cast_ray(
+            &map,
+            &game_obj_lib,
+            &game_lib,
+            Vec2::new(20.0, 180.0),
+            Vec2::new(180.0, 20.0),
+        );
+        assert_eq!(hit, diagonal_tile);
+    }
+
+    #[test]
+    fn ray_ending_on_an_edge_misses_obj() {
+        let game_lib = GameLib::new(CONFIG_PATH).unwrap();
+        let (map, game_obj_lib) = tile_map(&game_lib, &[Vec2::new(100.0, 100.0)]);
+
+        let hit = cast_ray(
+            &map,
+            &game_obj_lib,
+            &game_lib,
+            Vec2::new(20.0, 100.0),
+            Vec2::new(80.0, 100.0),
+        );
+        assert_eq!(hit, None);
+
+        let hit = cast_ray(
+            &map,
+            &game_obj_lib,
+            &game_lib,
+            Vec2::new(20.0, 100.0),
+            Vec2::new(81.0, 100.0),
+        );
+        assert!(hit.is_some());
+    }
+
+    #[test]
+    fn ray_grazing_a_corner_misses_obj() {
+        let game_lib = GameLib::new(CONFIG_PATH).unwrap();
+        let (map, game_obj_lib) = tile_map(&game_lib, &[Vec2::new(100.0, 100.0)]);
+
+        // Passes through the corner at (80, 120)
+        let hit = cast_ray(
+            &map,
+            &game_obj_lib,
+            &game_lib,
+            Vec2::new(40.0, 80.0),
+            Vec2::new(120.0, 160.0),
+        );
+        assert_eq!(hit, None);
+
+        let hit = cast_ray(
+            &map,
+            &game_obj_lib,
+            &game_lib,
+            Vec2::new(41.0, 80.0),
+            Vec2::new(121.0, 160.0),
+        );
+        assert!(hit.is_some());
+    }
+
+    #[test]
+    fn zero_length_ray_hits_only_inside_obj() {
+        let game_lib = GameLib::new(CONFIG_PATH).unwrap();
+        let (map, game_obj_lib) = tile_map(&game_lib, &[Vec2::new(100.0, 100.0)]);
+
+        let hit = cast_ray(
+            &map,
+            &game_obj_lib,
+            &game_lib,
+            Vec2::new(90.0, 100.0),
+            Vec2::new(90.0, 100.0),
+        );
+        assert!(hit.is_some());
+
+        let hit = cast_ray(
+            &map,
+            &game_obj_lib,
+            &game_lib,
+            Vec2::new(60.0, 100.0),
+            Vec2::new(60.0, 100.0),
+        );
+        assert_eq!(hit, None);
+    }
+
+    #[test]
+    fn ray_passes_through_allies() {
+        let game_lib = GameLib::new(CONFIG_PATH).unwrap();
+        let (mut map, mut game_obj_lib) = tile_map(&game_lib, &[]);
+        add_obj(
+            &mut map,
+            &mut game_obj_lib,
+            &game_lib,
+            "base",
+            Vec2::new(100.0, 20.0),
+        );
+
+        let hit = cast_ray(
+            &map,
+            &game_obj_lib,
+            &game_lib,
+            Vec2::new(20.0, 20.0),
+            Vec2::new(180.0, 20.0),
+        );
+        assert_eq!(hit, None);
+    }
+}
@@ -3,8 +3,9 @@ use crate::game_lib::*;
 use crate::game_map::*;
 use crate::utils::*;
 
+use bevy::ecs::entity::{EntityHashMap, EntityHashSet};
 use bevy::prelude::*;
-use std::collections::{HashMap, HashSet};
+use std::collections::BTreeMap;
 
 #[derive(Clone, Resource, Copy)]
 pub struct GameObjInfo {
@@ -12,11 +13,18 @@ pub struct GameObjInfo {
     pub pos: Vec2,
     pub map_pos: MapPos,
     pub direction: Vec2,
-    pub side: GameObjSide,
+    pub team: usize,
     pub obj_type: GameObjType,
+    pub terrain: Terrain,
     pub collide_span: f32,
     pub speed: f32,
     pub hp: Option<f32>,
+    pub invulnerable: bool,
+}
+
+#[derive(Component)]
+pub struct GameObjComponent {
+    pub config_index: usize,
 }
 
 #[derive(Component)]
@@ -26,7 +34,12 @@ pub struct TankComponent;
 pub struct MissileComponent;
 
 #[derive(Component)]
-pub struct PlayerComponent;
+pub struct BaseComponent;
+
+#[derive(Component)]
+pub struct PlayerComponent {
+    pub player_id: usize,
+}
 
 #[derive(Component)]
 pub struct ShootComponent {
@@ -38,7 +51,10 @@ pub struct ShootComponent {
 
 #[derive(Component)]
 pub struct ExplosionComponent {
+    pub explosion_name: String,
+    pub pos: Vec2,
     pub timer: Timer,
+    pub index: usize,
     pub last_index: usize,
 }
 
@@ -47,17 +63,48 @@ pub struct DeadGameObjInfo {
     pub is_phasing: bool,
 }
 
-#[derive(Component, Deref, DerefMut)]
-pub struct PhasingTimer(pub Timer);
+#[derive(Component)]
+pub struct PhasingComponent {
+    pub timer: Timer,
+    pub pos: Vec2,
+    pub direction: Vec2,
+    pub team: usize,
+}
 
 #[derive(Resource, Deref, DerefMut)]
-pub struct GameObjInfoLib(pub HashMap<Entity, GameObjInfo>);
+pub struct GameObjInfoLib(pub EntityHashMap<GameObjInfo>);
 
 #[derive(Resource, Deref, DerefMut)]
-pub struct DespawnPool(pub HashSet<Entity>);
+pub struct DespawnPool(pub EntityHashSet);
 
-#[derive(Resource)]
-pub struct PlayerInfo(pub Option<Entity>);
+#[derive(Message)]
+pub struct PlayerDied {
+    pub player_id: usize,
+    pub entity: Entity,
+    pub pos: Vec2,
+}
+
+#[derive(Message)]
+pub struct BaseDestroyed {
+    pub team: usize,
+    pub pos: Vec2,
+}
+
+#[derive(Component, Deref, DerefMut)]
+pub struct InvulnerableComponent(pub Timer);
+
+pub struct PlayerSlot {
+    pub entity: Entity,
+    pub config_index: usize,
+    pub team: usize,
+    pub spawn_pos: Vec2,
+    pub spawn_direction: Vec2,
+    pub lives: u32,
+    pub respawn_timer: Option<Timer>,
+}
+
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct PlayerInfo(pub BTreeMap<usize, PlayerSlot>);
 
 impl GameObjInfo {
     pub fn new(
@@ -67,53 +114,36 @@ impl GameObjInfo {
         direction: &Vec2,
         game_lib: &GameLib,
         commands: &mut Commands,
-    ) -> Option<(Self, Entity)> {
+    ) -> (Self, Entity) {
         let obj_config = &game_lib.get_obj_config(config_index);
-        let Some(entity) = Self::create_entity(pos, direction, obj_config, game_lib, commands)
-        else {
-            return None;
-        };
+        let entity =
+            Self::create_entity(config_index, pos, direction, obj_config, game_lib, commands);
         let obj = Self {
             config_index,
-            pos: pos.clone(),
-            map_pos: map_pos.clone(),
-            direction: direction.clone(),
-            side: obj_config.side,
+            pos: *pos,
+            map_pos: *map_pos,
+            direction: *direction,
+            team: game_lib.get_obj_team(config_index),
             obj_type: obj_config.obj_type,
+            terrain: obj_config.terrain,
             collide_span: obj_config.collide_span,
             speed: obj_config.speed,
-            hp: obj_config.max_hp.clone(),
+            hp: obj_config.max_hp,
+            invulnerable: false,
         };
 
-        Some((obj, entity))
+        (obj, entity)
     }
 
     fn create_entity(
+        config_index: usize,
         pos: &Vec2,
         direction: &Vec2,
         obj_config: &GameObjConfig,
         game_lib: &GameLib,
         commands: &mut Commands,
-    ) -> Option<Entity> {
-        let Some(image) = game_lib.get_image(&obj_config.image) else {
-            return None;
-        };
-        let size = arr_to_vec2(&obj_config.size);
-        let screen_pos = game_lib.get_screen_pos(&pos);
-
-        let mut entity = commands.spawn((
-            Sprite {
-                image,
-                custom_size: Some(size),
-                image_mode: SpriteImageMode::Scale(ScalingMode::FillCenter),
-                ..default()
-            },
-            Transform {
-                translation: Vec3::new(screen_pos.x, screen_pos.y, obj_config.z),
-                rotation: get_rotation(direction),
-                ..default()
-            },
-        ));
+    ) -> Entity {
+        let mut entity = commands.spawn(GameObjComponent { config_index });
 
         match obj_config.obj_type {
             GameObjType::Tank => {
@@ -122,30 +152,28 @@ impl GameObjInfo {
             GameObjType::Missile => {
                 entity.insert(MissileComponent);
             }
+            GameObjType::Base => {
+                entity.insert(BaseComponent);
+            }
             _ => (),
         }
 
-        if obj_config.side == GameObjSide::AI {
-            // if let Some(name) = obj_config.ai_config.as_ref() {
-            //     if let Some(ai_config) = game_lib.config.ai_configs.get(name) {
-            //         entity.insert(AIComponent::new(ai_config));
-            //     } else {
-            //         error!("Failed to find AIConfig {}", name);
-            //     }
-            // }
-        } else if obj_config.side == GameObjSide::Player {
-            entity.insert(PlayerComponent);
+        if let Some(name) = obj_config.ai_config.as_ref() {
+            if let Some(ai_config) = game_lib.get_ai_config(name) {
+                entity.insert(AIComponent::new(ai_config));
+            } else {
+                error!("Failed to find AIConfig {}", name);
+            }
         }
 
-        if let Some(shoot_config_name) = obj_config.shoot_config.as_ref() {
-            if let Some(shoot_comp) =
+        if let Some(shoot_config_name) = obj_config.shoot_config.as_ref()
+            && let Some(shoot_comp) =
                 Self::get_shoot_component(pos, direction, shoot_config_name, game_lib)
-            {
-                entity.insert(shoot_comp);
-            }
+        {
+            entity.insert(shoot_comp);
         }
 
-        Some(entity.id())
+        entity.id()
     }
 
     fn get_shoot_component(
@@ -177,8 +205,34 @@ impl GameObjInfo {
     }
 }
 
-impl PhasingTimer {
-    pub fn new(duration: f32) -> Self {
-        Self(Timer::from_seconds(duration, TimerMode::Once))
+impl ExplosionComponent {
+    pub fn new(pos: &Vec2, explosion_name: &str, explosion_config: &ExplosionConfig) -> Self {
+        let frame_duration = 1.0 / explosion_config.frames_per_second as f32;
+
+        Self {
+            explosion_name: explosion_name.to_string(),
+            pos: *pos,
+            timer: Timer::from_seconds(frame_duration, TimerMode::Repeating),
+            index: 0,
+            last_index: explosion_config.frame_count as usize,
+        }
+    }
+}
+
+impl PlayerSlot {
+    // A player waiting to respawn is still in the game
+    pub fn is_in_game(&self, game_obj_lib: &GameObjInfoLib) -> bool {
+        game_obj_lib.contains_key(&self.entity) || self.respawn_timer.is_some()
+    }
+}
+
+impl PhasingComponent {
+    pub fn new(duration: f32, pos: &Vec2, direction: &Vec2, team: usize) -> Self {
+        Self {
+            timer: Timer::from_seconds(duration, TimerMode::Once),
+            pos: *pos,
+            direction: *direction,
+            team,
+        }
     }
 }
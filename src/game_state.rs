@@ -0,0 +1,254 @@
+use crate::game_lib::*;
+use crate::game_obj::*;
+use crate::objective::*;
+use crate::replay::*;
+use crate::simulation::*;
+use crate::spawner::*;
+use crate::systems::*;
+use crate::utils::*;
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+#[derive(States, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum GameState {
+    #[default]
+    Title,
+    Playing,
+    Paused,
+    GameOver,
+    Victory,
+}
+
+#[derive(
+    Resource, ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default,
+)]
+pub enum GameMode {
+    #[default]
+    Coop,
+    Versus,
+}
+
+impl GameMode {
+    // In versus mode each player id takes its team from versus_teams
+    pub fn get_player_team(&self, player_id: usize, game_lib: &GameLib) -> Option<usize> {
+        match self {
+            GameMode::Coop => None,
+            GameMode::Versus => game_lib
+                .get_game_config()
+                .versus_teams
+                .get(player_id)
+                .and_then(|name| game_lib.get_team_index(name)),
+        }
+    }
+}
+
+// Everything the outcome of a game is decided from
+#[derive(SystemParam)]
+pub struct GameProgressParams<'w> {
+    mode: Res<'w, GameMode>,
+    game_lib: Res<'w, GameLib>,
+    player_info: Res<'w, PlayerInfo>,
+    spawner_info: Res<'w, SpawnerInfo>,
+    objective_info: Res<'w, ObjectiveInfo>,
+    game_obj_lib: Res<'w, GameObjInfoLib>,
+}
+
+pub fn check_game_result(
+    progress: GameProgressParams,
+    dying_player_query: Query<(), (With<PlayerComponent>, With<PhasingComponent>)>,
+    mut map_outcome: MessageReader<MapOutcome>,
+    mut base_destroyed: MessageReader<BaseDestroyed>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let GameProgressParams {
+        mode,
+        game_lib,
+        player_info,
+        spawner_info,
+        objective_info,
+        game_obj_lib,
+    } = progress;
+    let alive_teams = get_player_teams(player_info.as_ref(), game_obj_lib.as_ref());
+
+    if alive_teams.is_empty() {
+        // Let the last player finish phasing out before the game ends
+        if dying_player_query.is_empty() {
+            next_state.set(GameState::GameOver);
+        }
+        return;
+    }
+
+    // Losing a base ends the match for the players allied with it; in versus
+    // the other players win
+    for msg in base_destroyed.read() {
+        let is_allied = |team: &usize| game_lib.get_relation(*team, msg.team) == Relation::Ally;
+        if !alive_teams.iter().any(is_allied) {
+            continue;
+        }
+
+        info!(
+            "Base of team {} destroyed at {:?}",
+            game_lib.get_team_name(msg.team),
+            msg.pos
+        );
+        next_state.set(
+            if *mode == GameMode::Versus && !alive_teams.iter().all(is_allied) {
+                GameState::Victory
+            } else {
+                GameState::GameOver
+            },
+        );
+        return;
+    }
+
+    if let Some(outcome) = map_outcome.read().last() {
+        next_state.set(match outcome {
+            MapOutcome::Won => GameState::Victory,
+            MapOutcome::Lost => GameState::GameOver,
+        });
+        return;
+    }
+
+    // Objectives that can be won replace the default win conditions, maps
+    // that only protect objects keep them
+    if objective_info.can_be_won() {
+        return;
+    }
+
+    let won = match *mode {
+        GameMode::Coop => {
+            spawner_info.is_finished()
+                && !has_enemy_tank(&alive_teams, game_lib.as_ref(), game_obj_lib.as_ref())
+        }
+        GameMode::Versus => {
+            alive_teams.len() < player_info.len()
+                && !alive_teams
+                    .iter()
+                    .any(|t| is_enemy_of(&alive_teams, *t, game_lib.as_ref()))
+        }
+    };
+
+    if won {
+        next_state.set(GameState::Victory);
+    }
+}
+
+// Teams of the players that are alive or waiting to respawn
+pub fn get_player_teams(player_info: &PlayerInfo, game_obj_lib: &GameObjInfoLib) -> Vec<usize> {
+    player_info
+        .values()
+        .filter(|slot| slot.is_in_game(game_obj_lib))
+        .map(|slot| slot.team)
+        .collect()
+}
+
+pub fn has_enemy_tank(
+    player_teams: &[usize],
+    game_lib: &GameLib,
+    game_obj_lib: &GameObjInfoLib,
+) -> bool {
+    game_obj_lib.values().any(|obj| {
+        obj.obj_type == GameObjType::Tank && is_enemy_of(player_teams, obj.team, game_lib)
+    })
+}
+
+#[inline]
+fn is_enemy_of(player_teams: &[usize], team: usize, game_lib: &GameLib) -> bool {
+    player_teams
+        .iter()
+        .any(|player_team| game_lib.get_relation(*player_team, team) == Relation::Enemy)
+}
+
+pub fn handle_state_keys(
+    keys: Res<ButtonInput<KeyCode>>,
+    gamepads: Query<&Gamepad>,
+    game_lib: Res<GameLib>,
+    state: Res<State<GameState>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let game_config = game_lib.get_game_config();
+    let pause_keys = &game_config.key_bindings.pause;
+    let pause_buttons = &game_config.gamepad_bindings.pause;
+    let pause_pressed = keys.any_just_pressed(pause_keys.iter().copied())
+        || gamepads
+            .iter()
+            .any(|gamepad| gamepad.any_just_pressed(pause_buttons.iter().copied()));
+
+    match state.get() {
+        GameState::Title if keys.just_pressed(KeyCode::Enter) => {
+            next_state.set(GameState::Playing);
+        }
+        GameState::Playing if pause_pressed => {
+            next_state.set(GameState::Paused);
+        }
+        GameState::Paused if pause_pressed => {
+            next_state.set(GameState::Playing);
+        }
+        _ => (),
+    }
+}
+
+// Replays restart where the recording did, whatever keys are pressed
+pub fn restart_game(
+    keys: Option<Res<ButtonInput<KeyCode>>>,
+    header: Res<ReplayHeader>,
+    mut replay_player: Option<ResMut<ReplayPlayer>>,
+    mut recorder: Option<ResMut<ReplayRecorder>>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut reloader: GameWorldReloader,
+) {
+    let restart = match replay_player.as_mut() {
+        Some(replay_player) => replay_player.take_restart(),
+        None => keys.is_some_and(|keys| keys.just_pressed(KeyCode::Enter)),
+    };
+    if !restart {
+        return;
+    }
+
+    // Same order as setup_game, so a saved game keeps its own tick and RNG
+    reloader.commands.insert_resource(SimTick::default());
+    reloader.commands.insert_resource(GameRng::new(header.seed));
+    if let Err(err) = reloader.reload(&header.map_path, header.mode) {
+        error!("Failed to restart game from {:?}: {}", header.map_path, err);
+        return;
+    }
+    next_state.set(GameState::Playing);
+
+    if let Some(recorder) = recorder.as_mut() {
+        let index = recorder.replay.inputs.len();
+        recorder.replay.restarts.push(index);
+    }
+
+    info!("Restarted game");
+}
+
+pub fn report_game_result(
+    args: Res<Args>,
+    state: Res<State<GameState>>,
+    sim_tick: Res<SimTick>,
+    player_info: Res<PlayerInfo>,
+    game_obj_lib: Res<GameObjInfoLib>,
+    replay_player: Option<Res<ReplayPlayer>>,
+    mut exit_app: MessageWriter<AppExit>,
+) {
+    let survivors: Vec<usize> = player_info
+        .iter()
+        .filter(|(_, slot)| slot.is_in_game(game_obj_lib.as_ref()))
+        .map(|(player_id, _)| *player_id)
+        .collect();
+
+    info!(
+        "{:?} after {} ticks, surviving players {:?}, state digest {:016x}",
+        state.get(),
+        sim_tick.0,
+        survivors,
+        get_state_digest(game_obj_lib.as_ref())
+    );
+
+    if args.headless && !replay_player.is_some_and(|p| p.is_restart_due()) {
+        exit_app.write(AppExit::Success);
+    }
+}
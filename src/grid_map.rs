@@ -0,0 +1,352 @@
+use crate::game_lib::*;
+use crate::game_map::*;
+use crate::my_error::*;
+use crate::utils::*;
+
+use bevy::prelude::*;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+pub const EMPTY_CELL: char = '.';
+const LEGEND_CHARS: &str =
+    "#ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789@$%&*+=~^";
+
+// The first row is the top of the map. Every character other than '.' or a
+// space places the legend's object at the center of its cell.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GameMapGridConfig {
+    pub legend: BTreeMap<char, GameMapLegendEntry>,
+    pub rows: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct GameMapLegendEntry {
+    pub config_name: String,
+    #[serde(default)]
+    pub direction: Direction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub player_id: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team: Option<String>,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapFormat {
+    Grid,
+    Objs,
+}
+
+impl GameMapConfig {
+    pub fn expand_grid(&mut self, game_config: &GameConfig) -> Result<(), MyError> {
+        let Some(grid) = self.grid.take() else {
+            return Ok(());
+        };
+
+        let row_count = game_config.map_row_count();
+        let col_count = game_config.map_col_count();
+        if grid.rows.len() > row_count {
+            return Err(MyError::Map(format!(
+                "Map grid has {} rows, more than the map's {}",
+                grid.rows.len(),
+                row_count
+            )));
+        }
+
+        if let Some(c) = grid.legend.keys().find(|c| **c == EMPTY_CELL || **c == ' ') {
+            return Err(MyError::Map(format!(
+                "Map grid legend uses '{}', which always marks an empty cell",
+                c
+            )));
+        }
+
+        let mut objs = Vec::new();
+        for (i, line) in grid.rows.iter().enumerate() {
+            let row = row_count - 1 - i;
+            for (col, c) in line.chars().enumerate() {
+                if c == EMPTY_CELL || c == ' ' {
+                    continue;
+                }
+                if col >= col_count {
+                    return Err(MyError::Map(format!(
+                        "Map grid row {} is longer than the map's {} columns",
+                        i, col_count
+                    )));
+                }
+                let Some(entry) = grid.legend.get(&c) else {
+                    return Err(MyError::Map(format!(
+                        "Map grid character '{}' at row {} column {} is not in the legend",
+                        c, i, col
+                    )));
+                };
+
+                objs.push(GameMapObjConfig {
+                    player_id: entry.player_id,
+                    team: entry.team.clone(),
+                    origin: Some(GameMapObjOrigin::Grid { row: i, col }),
+                    ..GameMapObjConfig::new(
+                        entry.config_name.clone(),
+                        get_cell_center(row, col, game_config.map_cell_size).to_array(),
+                        entry.direction,
+                    )
+                });
+            }
+        }
+
+        objs.append(&mut self.objs);
+        self.objs = objs;
+
+        Ok(())
+    }
+
+    /// Moves every object that sits on a cell center and needs nothing beyond
+    /// a legend entry into the grid. The others stay in the object list.
+    pub fn collapse_to_grid(&mut self, game_config: &GameConfig) -> Result<(), MyError> {
+        self.expand_grid(game_config)?;
+
+        let row_count = game_config.map_row_count();
+        let col_count = game_config.map_col_count();
+        let cell_size = game_config.map_cell_size;
+        let mut cells = vec![vec![EMPTY_CELL; col_count]; row_count];
+        let mut legend: BTreeMap<char, GameMapLegendEntry> = BTreeMap::new();
+        let mut legend_chars: HashMap<GameMapLegendEntry, char> = HashMap::new();
+        let mut objs = Vec::new();
+
+        for obj in std::mem::take(&mut self.objs) {
+            let pos = arr_to_vec2(&obj.pos);
+            let row = (pos.y / cell_size).floor();
+            let col = (pos.x / cell_size).floor();
+            let fits = row >= 0.0
+                && col >= 0.0
+                && (row as usize) < row_count
+                && (col as usize) < col_count
+                && get_cell_center(row as usize, col as usize, cell_size) == pos
+                && cells[row as usize][col as usize] == EMPTY_CELL
+                && obj.name.is_none()
+                && obj.hp.is_none()
+                && obj.shoot_elapsed.is_none()
+                && obj.lives.is_none()
+                && obj.respawn_elapsed.is_none()
+                && obj.invulnerable_elapsed.is_none()
+                && obj.spawner.is_none()
+                && obj.ai.is_none();
+            if !fits {
+                objs.push(obj);
+                continue;
+            }
+
+            let entry = GameMapLegendEntry {
+                config_name: obj.config_name.clone(),
+                direction: obj.direction,
+                player_id: obj.player_id,
+                team: obj.team.clone(),
+            };
+            let c = match legend_chars.get(&entry) {
+                Some(c) => *c,
+                None => {
+                    let Some(c) = get_legend_char(&entry.config_name, &legend) else {
+                        objs.push(obj);
+                        continue;
+                    };
+                    legend_chars.insert(entry.clone(), c);
+                    legend.insert(c, entry);
+                    c
+                }
+            };
+            cells[row as usize][col as usize] = c;
+        }
+
+        let rows: Vec<String> = cells.iter().rev().map(|row| row.iter().collect()).collect();
+
+        self.objs = objs;
+        self.grid = (!legend.is_empty()).then_some(GameMapGridConfig { legend, rows });
+
+        Ok(())
+    }
+}
+
+pub fn convert_map<P: AsRef<Path>>(
+    config_path: P,
+    map_path: P,
+    format: MapFormat,
+    output_path: P,
+) -> Result<(), MyError> {
+    let game_lib = GameLib::new(config_path)?;
+    let mut map_config = GameMapConfig::read(map_path.as_ref(), game_lib.get_game_config())?;
+
+    if format == MapFormat::Grid {
+        map_config.collapse_to_grid(game_lib.get_game_config())?;
+    }
+
+    let file = File::create(output_path.as_ref())?;
+    serde_json::to_writer_pretty(BufWriter::new(file), &map_config)?;
+
+    info!(
+        "Converted {:?} to {:?} in {:?} format",
+        map_path.as_ref(),
+        output_path.as_ref(),
+        format
+    );
+
+    Ok(())
+}
+
+// Prefers the initial of the config name so the grid stays readable
+fn get_legend_char(config_name: &str, legend: &BTreeMap<char, GameMapLegendEntry>) -> Option<char> {
+    let initial = config_name
+        .chars()
+        .next()
+        .filter(|c| c.is_ascii_alphabetic());
+    initial
+        .into_iter()
+        .flat_map(|c| [c.to_ascii_uppercase(), c.to_ascii_lowercase()])
+        .chain(LEGEND_CHARS.chars())
+        .find(|c| !legend.contains_key(c))
+}
+
+#[inline]
+pub fn get_cell_center(row: usize, col: usize, cell_size: f32) -> Vec2 {
+    Vec2::new(
+        (col as f32 + 0.5) * cell_size,
+        (row as f32 + 0.5) * cell_size,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_game_config() -> GameConfig {
+        read_json(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/assets/game_config.json"
+        ))
+        .unwrap()
+    }
+
+    fn to_sorted_json(objs: &[GameMapObjConfig]) -> Vec<String> {
+        let mut objs: Vec<String> = objs
+            .iter()
+            .map(|obj| serde_json::to_string(obj).unwrap())
+            .collect();
+        objs.sort();
+        objs
+    }
+
+    #[test]
+    fn grid_round_trip_keeps_objs() {
+        let game_config = read_game_config();
+        let mut player_tank =
+            GameMapObjConfig::new("player_tank".to_string(), [60.0, 60.0], Direction::Up);
+        player_tank.player_id = Some(1);
+        let mut named_tile =
+            GameMapObjConfig::new("brick_tile".to_string(), [300.0, 300.0], Direction::Up);
+        named_tile.name = Some("gate".to_string());
+        let map_config = GameMapConfig {
+            objs: vec![
+                GameMapObjConfig::new("steel_tile".to_string(), [20.0, 20.0], Direction::Up),
+                GameMapObjConfig::new("steel_tile".to_string(), [60.0, 20.0], Direction::Up),
+                GameMapObjConfig::new("brick_tile".to_string(), [20.0, 780.0], Direction::Left),
+                player_tank,
+                GameMapObjConfig::new("ai_tank".to_string(), [110.0, 100.0], Direction::Down),
+                named_tile,
+            ],
+            ..Default::default()
+        };
+
+        let mut grid_config = map_config.clone();
+        grid_config.collapse_to_grid(&game_config).unwrap();
+        assert!(grid_config.grid.is_some());
+        assert_eq!(grid_config.objs.len(), 2);
+
+        let json = serde_json::to_string(&grid_config).unwrap();
+        let mut objs_config: GameMapConfig = serde_json::from_str(&json).unwrap();
+        objs_config.expand_grid(&game_config).unwrap();
+        assert!(objs_config.grid.is_none());
+        assert_eq!(
+            to_sorted_json(&objs_config.objs),
+            to_sorted_json(&map_config.objs)
+        );
+    }
+
+    #[test]
+    fn saved_ai_state_stays_in_objs() {
+        let game_config = read_game_config();
+        let mut ai_tank =
+            GameMapObjConfig::new("ai_tank".to_string(), [60.0, 60.0], Direction::Down);
+        ai_tank.ai = Some(GameMapAIConfig {
+            action: None,
+            collision_happened: true,
+            keep_direction_elapsed: 0.5,
+            move_elapsed: 0.25,
+            shoot_elapsed: 1.0,
+            path: Vec::new(),
+        });
+        let mut map_config = GameMapConfig {
+            objs: vec![
+                GameMapObjConfig::new("steel_tile".to_string(), [20.0, 20.0], Direction::Up),
+                ai_tank,
+            ],
+            ..Default::default()
+        };
+
+        map_config.collapse_to_grid(&game_config).unwrap();
+        assert_eq!(map_config.objs.len(), 1);
+        let ai = map_config.objs[0].ai.as_ref().unwrap();
+        assert!(ai.collision_happened);
+        assert_eq!(ai.move_elapsed, 0.25);
+    }
+
+    #[test]
+    fn expanded_grid_objs_know_their_cell() {
+        let game_config = read_game_config();
+        let mut map_config = GameMapConfig {
+            objs: vec![GameMapObjConfig::new(
+                "steel_tile".to_string(),
+                [60.0, 20.0],
+                Direction::Up,
+            )],
+            ..Default::default()
+        };
+        map_config.collapse_to_grid(&game_config).unwrap();
+        map_config.expand_grid(&game_config).unwrap();
+
+        assert_eq!(
+            map_config.objs[0].origin,
+            Some(GameMapObjOrigin::Grid {
+                row: game_config.map_row_count() - 1,
+                col: 1
+            })
+        );
+    }
+
+    #[test]
+    fn legend_keys_for_empty_cells_are_rejected() {
+        let game_config = read_game_config();
+        for c in [EMPTY_CELL, ' '] {
+            let mut map_config = GameMapConfig {
+                grid: Some(GameMapGridConfig {
+                    legend: BTreeMap::from([(
+                        c,
+                        GameMapLegendEntry {
+                            config_name: "steel_tile".to_string(),
+                            direction: Direction::Up,
+                            player_id: None,
+                            team: None,
+                        },
+                    )]),
+                    rows: vec![format!("{}{}", c, c)],
+                }),
+                ..Default::default()
+            };
+
+            assert!(matches!(
+                map_config.expand_grid(&game_config),
+                Err(MyError::Map(_))
+            ));
+        }
+    }
+}
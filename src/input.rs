@@ -0,0 +1,302 @@
+use crate::game_lib::*;
+use crate::game_map::*;
+use crate::my_error::*;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PlayerInput {
+    pub move_direction: Option<Direction>,
+    pub shoot: bool,
+}
+
+// One input per player, indexed by player id
+#[derive(Resource, Default, Debug, Clone, PartialEq, Deref, DerefMut, Serialize, Deserialize)]
+pub struct PlayerInputs(pub Vec<PlayerInput>);
+
+#[derive(Debug, Deserialize)]
+pub struct PlayerKeyBindings {
+    pub move_up: Vec<KeyCode>,
+    pub move_down: Vec<KeyCode>,
+    pub move_left: Vec<KeyCode>,
+    pub move_right: Vec<KeyCode>,
+    pub fire: Vec<KeyCode>,
+}
+
+// Keys the game handles itself, which cannot be bound to an action
+const RESERVED_KEYS: [(KeyCode, &str); 1] = [(KeyCode::Enter, "start and restart")];
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct KeyBindings {
+    pub players: Vec<PlayerKeyBindings>,
+    pub pause: Vec<KeyCode>,
+    pub quick_save: Vec<KeyCode>,
+    pub quick_load: Vec<KeyCode>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            players: vec![
+                PlayerKeyBindings {
+                    move_up: vec![KeyCode::ArrowUp],
+                    move_down: vec![KeyCode::ArrowDown],
+                    move_left: vec![KeyCode::ArrowLeft],
+                    move_right: vec![KeyCode::ArrowRight],
+                    fire: vec![KeyCode::KeyF],
+                },
+                PlayerKeyBindings {
+                    move_up: vec![KeyCode::KeyW],
+                    move_down: vec![KeyCode::KeyS],
+                    move_left: vec![KeyCode::KeyA],
+                    move_right: vec![KeyCode::KeyD],
+                    fire: vec![KeyCode::Space],
+                },
+            ],
+            pause: vec![KeyCode::KeyP, KeyCode::Escape],
+            quick_save: vec![KeyCode::F5],
+            quick_load: vec![KeyCode::F9],
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct GamepadBindings {
+    pub fire: Vec<GamepadButton>,
+    pub pause: Vec<GamepadButton>,
+    pub dead_zone: f32,
+}
+
+impl Default for GamepadBindings {
+    fn default() -> Self {
+        Self {
+            fire: vec![GamepadButton::South],
+            pause: vec![GamepadButton::Start],
+            dead_zone: 0.3,
+        }
+    }
+}
+
+impl KeyBindings {
+    pub fn validate(&self) -> Result<(), MyError> {
+        if self.players.is_empty() {
+            return Err(MyError::Config("No player key bindings".to_string()));
+        }
+
+        let mut actions = vec![
+            ("pause".to_string(), &self.pause),
+            ("quick_save".to_string(), &self.quick_save),
+            ("quick_load".to_string(), &self.quick_load),
+        ];
+        for (player_id, bindings) in self.players.iter().enumerate() {
+            actions.extend(
+                bindings
+                    .actions()
+                    .map(|(action, keys)| (format!("player {} {}", player_id, action), keys)),
+            );
+        }
+
+        let mut bound_keys: HashMap<KeyCode, &String> = HashMap::new();
+        for (action, keys) in actions.iter() {
+            if keys.is_empty() {
+                return Err(MyError::Config(format!("No key bound to {}", action)));
+            }
+
+            for key in keys.iter() {
+                if let Some((_, reserved)) = RESERVED_KEYS.iter().find(|(k, _)| k == key) {
+                    return Err(MyError::Config(format!(
+                        "Key {:?} bound to {} is reserved for {}",
+                        key, action, reserved
+                    )));
+                }
+                if let Some(other) = bound_keys.insert(*key, action) {
+                    return Err(MyError::Config(format!(
+                        "Key {:?} is bound to both {} and {}",
+                        key, other, action
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl PlayerKeyBindings {
+    pub fn get_input(&self, keys: &ButtonInput<KeyCode>) -> PlayerInput {
+        let pressed = |bound_keys: &Vec<KeyCode>| keys.any_pressed(bound_keys.iter().copied());
+
+        let move_direction = if pressed(&self.move_right) {
+            Some(Direction::Right)
+        } else if pressed(&self.move_left) {
+            Some(Direction::Left)
+        } else if pressed(&self.move_up) {
+            Some(Direction::Up)
+        } else if pressed(&self.move_down) {
+            Some(Direction::Down)
+        } else {
+            None
+        };
+
+        PlayerInput {
+            move_direction,
+            shoot: pressed(&self.fire),
+        }
+    }
+
+    fn actions(&self) -> [(&'static str, &Vec<KeyCode>); 5] {
+        [
+            ("move_up", &self.move_up),
+            ("move_down", &self.move_down),
+            ("move_left", &self.move_left),
+            ("move_right", &self.move_right),
+            ("fire", &self.fire),
+        ]
+    }
+}
+
+impl GamepadBindings {
+    pub fn validate(&self) -> Result<(), MyError> {
+        if !(0.0..1.0).contains(&self.dead_zone) {
+            return Err(MyError::Config(format!(
+                "Gamepad dead zone {} is not in [0, 1)",
+                self.dead_zone
+            )));
+        }
+
+        if self.fire.is_empty() {
+            return Err(MyError::Config(
+                "No gamepad button bound to fire".to_string(),
+            ));
+        }
+
+        if let Some(button) = self.fire.iter().find(|b| self.pause.contains(b)) {
+            return Err(MyError::Config(format!(
+                "Gamepad button {:?} is bound to both fire and pause",
+                button
+            )));
+        }
+
+        Ok(())
+    }
+
+    // The stick snaps to the nearest cardinal direction since movement is
+    // axis-aligned
+    pub fn get_direction(&self, gamepad: &Gamepad) -> Option<Direction> {
+        let dpad = gamepad.dpad();
+        let stick = gamepad.left_stick();
+
+        if dpad != Vec2::ZERO {
+            Some(dpad.into())
+        } else if stick.length() > self.dead_zone {
+            Some(stick.into())
+        } else {
+            None
+        }
+    }
+}
+
+// Gamepads are handed to players by entity, so each player keeps the same
+// gamepad for as long as the connected set does not change
+pub fn read_player_input(
+    keys: Res<ButtonInput<KeyCode>>,
+    gamepads: Query<(Entity, &Gamepad)>,
+    game_lib: Res<GameLib>,
+    mut player_inputs: ResMut<PlayerInputs>,
+) {
+    let game_config = game_lib.get_game_config();
+    let gamepad_bindings = &game_config.gamepad_bindings;
+    let mut gamepads: Vec<_> = gamepads.iter().collect();
+    gamepads.sort_by_key(|(entity, _)| *entity);
+
+    player_inputs.0 = game_config
+        .key_bindings
+        .players
+        .iter()
+        .enumerate()
+        .map(|(player_id, bindings)| {
+            let mut input = bindings.get_input(keys.as_ref());
+            if let Some((_, gamepad)) = gamepads.get(player_id) {
+                input.move_direction = input
+                    .move_direction
+                    .or_else(|| gamepad_bindings.get_direction(gamepad));
+                input.shoot |= gamepad.any_pressed(gamepad_bindings.fire.iter().copied());
+            }
+            input
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::*;
+
+    fn get_error(key_bindings: &KeyBindings) -> String {
+        match key_bindings.validate() {
+            Err(MyError::Config(message)) => message,
+            result => panic!("Expected a config error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn default_bindings_are_valid() {
+        assert!(KeyBindings::default().validate().is_ok());
+    }
+
+    #[test]
+    fn shipped_bindings_are_valid() {
+        let game_config: GameConfig = read_json(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/assets/game_config.json"
+        ))
+        .unwrap();
+        assert!(game_config.key_bindings.validate().is_ok());
+    }
+
+    #[test]
+    fn duplicate_binding_is_rejected() {
+        let mut key_bindings = KeyBindings::default();
+        key_bindings.players[1].fire.push(KeyCode::KeyF);
+        assert_eq!(
+            get_error(&key_bindings),
+            "Key KeyF is bound to both player 0 fire and player 1 fire"
+        );
+
+        let key_bindings = KeyBindings {
+            quick_load: vec![KeyCode::F5],
+            ..Default::default()
+        };
+        assert_eq!(
+            get_error(&key_bindings),
+            "Key F5 is bound to both quick_save and quick_load"
+        );
+    }
+
+    #[test]
+    fn reserved_key_is_rejected() {
+        let mut key_bindings = KeyBindings::default();
+        key_bindings.pause.push(KeyCode::Enter);
+        assert_eq!(
+            get_error(&key_bindings),
+            "Key Enter bound to pause is reserved for start and restart"
+        );
+    }
+
+    #[test]
+    fn missing_binding_is_rejected() {
+        let mut key_bindings = KeyBindings::default();
+        key_bindings.players[0].move_up.clear();
+        assert_eq!(get_error(&key_bindings), "No key bound to player 0 move_up");
+
+        let key_bindings = KeyBindings {
+            players: Vec::new(),
+            ..Default::default()
+        };
+        assert_eq!(get_error(&key_bindings), "No player key bindings");
+    }
+}
@@ -0,0 +1,60 @@
+pub mod ai;
+pub mod editor;
+pub mod game_lib;
+pub mod game_map;
+pub mod game_obj;
+pub mod game_state;
+pub mod grid_map;
+pub mod input;
+pub mod my_error;
+pub mod objective;
+pub mod pathfinding;
+pub mod render;
+pub mod replay;
+pub mod save;
+pub mod simulation;
+pub mod spawner;
+pub mod systems;
+pub mod tiled;
+pub mod utils;
+pub mod validation;
+
+use crate::editor::*;
+use crate::game_state::*;
+use crate::render::*;
+use crate::simulation::*;
+use crate::utils::*;
+use bevy::{log::LogPlugin, prelude::*, state::app::StatesPlugin, time::TimeUpdateStrategy};
+
+pub fn create_app(args: Args) -> App {
+    let mut app = App::new();
+
+    if args.edit {
+        app.add_plugins(DefaultPlugins.build().disable::<LogPlugin>())
+            .add_plugins(PresentationPlugin)
+            .insert_resource(args)
+            .add_plugins(EditorPlugin);
+        return app;
+    }
+
+    if args.headless {
+        let timestep = Time::<Fixed>::default().timestep();
+        app.add_plugins((MinimalPlugins, StatesPlugin))
+            .insert_resource(TimeUpdateStrategy::ManualDuration(timestep))
+            .insert_state(GameState::Playing);
+    } else {
+        app.add_plugins(DefaultPlugins.build().disable::<LogPlugin>())
+            .add_plugins(PresentationPlugin);
+
+        // Replays play back without waiting on the title screen
+        if args.replay_path.is_some() {
+            app.insert_state(GameState::Playing);
+        } else {
+            app.init_state::<GameState>();
+        }
+    }
+
+    app.insert_resource(args).add_plugins(SimulationPlugin);
+
+    app
+}
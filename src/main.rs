@@ -1,34 +1,53 @@
-mod ai;
-mod game_lib;
-mod game_map;
-mod game_obj;
-mod my_error;
-mod systems;
-mod utils;
-
-use crate::systems::*;
-use crate::utils::*;
-use bevy::{log::LogPlugin, prelude::*};
 use clap::Parser;
+use std::process::ExitCode;
+use tank_rs::create_app;
+use tank_rs::grid_map::*;
+use tank_rs::utils::*;
+use tank_rs::validation::*;
 
-fn main() {
+fn main() -> ExitCode {
     let args = Args::parse();
-    let _guard = setup_log(&args.log_path);
+    let _guard = args.log_path.as_ref().map(setup_log);
+
+    match args.command.as_ref() {
+        Some(Command::Convert {
+            config_path,
+            map_path,
+            to,
+            output,
+        }) => {
+            if let Err(err) = convert_map(config_path, map_path, *to, output) {
+                eprintln!("Failed to convert map {:?}: {}", map_path, err);
+                return ExitCode::FAILURE;
+            }
+            ExitCode::SUCCESS
+        }
+        Some(Command::Validate {
+            config_path,
+            map_path,
+        }) => {
+            let problems = match validate_map_file(config_path, map_path) {
+                Ok(problems) => problems,
+                Err(err) => {
+                    eprintln!("Failed to validate map {:?}: {}", map_path, err);
+                    return ExitCode::FAILURE;
+                }
+            };
+
+            if problems.is_empty() {
+                eprintln!("Map {:?} is valid", map_path);
+                return ExitCode::SUCCESS;
+            }
 
-    App::new()
-        .add_plugins(DefaultPlugins.build().disable::<LogPlugin>())
-        .insert_resource(args)
-        .add_systems(Startup, setup_game)
-        // .add_systems(FixedUpdate, update_ai)
-        .add_systems(
-            Update,
-            (
-                process_input,
-                update_missiles,
-                update_explosions,
-                update_phasing_objs,
-            ),
-        )
-        .add_systems(PostUpdate, cleanup)
-        .run();
+            for problem in problems.iter() {
+                eprintln!("{:?}: {}", map_path, problem);
+            }
+            eprintln!("Map {:?} has {} problems", map_path, problems.len());
+            ExitCode::FAILURE
+        }
+        None => {
+            create_app(args).run();
+            ExitCode::SUCCESS
+        }
+    }
 }
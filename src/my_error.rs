@@ -1,6 +1,3 @@
-use serde_json;
-use thiserror;
-
 #[derive(Debug, thiserror::Error)]
 pub enum MyError {
     #[error("IO error: {0}")]
@@ -8,4 +5,13 @@ pub enum MyError {
 
     #[error("JSON error: {0}")]
     Json(#[from] serde_json::Error),
+
+    #[error("Invalid arguments: {0}")]
+    Args(String),
+
+    #[error("Invalid config: {0}")]
+    Config(String),
+
+    #[error("Invalid map: {0}")]
+    Map(String),
 }
@@ -0,0 +1,175 @@
+use crate::game_lib::*;
+use crate::game_map::*;
+use crate::game_obj::*;
+use crate::game_state::*;
+use crate::spawner::*;
+use crate::utils::*;
+
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+pub struct Objective {
+    pub config: GameMapObjectiveConfig,
+    pub target: Option<Entity>,
+    pub completed: bool,
+}
+
+#[derive(Resource, Default)]
+pub struct ObjectiveInfo {
+    pub objectives: Vec<Objective>,
+}
+
+#[derive(Message, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapOutcome {
+    Won,
+    Lost,
+}
+
+impl Objective {
+    fn new(config: &GameMapObjectiveConfig, obj_names: &HashMap<String, Entity>) -> Self {
+        let target = match config {
+            GameMapObjectiveConfig::Protect { obj_name } => {
+                let target = obj_names.get(obj_name).copied();
+                if target.is_none() {
+                    warn!(
+                        "Failed to find object {} to protect, objective is ignored",
+                        obj_name
+                    );
+                }
+                target
+            }
+            _ => None,
+        };
+
+        Self {
+            config: config.clone(),
+            target,
+            completed: false,
+        }
+    }
+
+    // Protecting an object can only lose the game
+    #[inline]
+    pub fn is_lose_only(&self) -> bool {
+        matches!(self.config, GameMapObjectiveConfig::Protect { .. })
+    }
+
+    // A protected object keeps its objective completed until it is destroyed
+    fn update(
+        &mut self,
+        player_teams: &[usize],
+        player_info: &PlayerInfo,
+        spawner_info: &SpawnerInfo,
+        game_lib: &GameLib,
+        game_obj_lib: &GameObjInfoLib,
+        time: &Time,
+    ) -> bool {
+        match &mut self.config {
+            GameMapObjectiveConfig::DestroyAllEnemies => {
+                spawner_info.is_finished() && !has_enemy_tank(player_teams, game_lib, game_obj_lib)
+            }
+            GameMapObjectiveConfig::Survive { duration, elapsed } => {
+                let elapsed = elapsed.get_or_insert(0.0);
+                *elapsed += time.delta_secs();
+                *elapsed >= *duration
+            }
+            GameMapObjectiveConfig::Protect { .. } => self
+                .target
+                .is_none_or(|target| game_obj_lib.contains_key(&target)),
+            GameMapObjectiveConfig::ReachZone { pos, size, reached } => {
+                if reached.is_none_or(|r| !r) {
+                    let center = arr_to_vec2(pos);
+                    let half_size = arr_to_vec2(size) / 2.0;
+                    let inside = player_info
+                        .values()
+                        .filter_map(|slot| game_obj_lib.get(&slot.entity))
+                        .any(|obj| (obj.pos - center).abs().cmple(half_size).all());
+                    if inside {
+                        *reached = Some(true);
+                    }
+                }
+                reached.unwrap_or_default()
+            }
+        }
+    }
+}
+
+impl ObjectiveInfo {
+    pub fn from_config(
+        objective_configs: &[GameMapObjectiveConfig],
+        obj_names: &HashMap<String, Entity>,
+    ) -> Self {
+        Self {
+            objectives: objective_configs
+                .iter()
+                .map(|config| Objective::new(config, obj_names))
+                .collect(),
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.objectives.is_empty()
+    }
+
+    pub fn can_be_won(&self) -> bool {
+        self.objectives.iter().any(|o| !o.is_lose_only())
+    }
+
+    pub fn get_save_configs(&self) -> Vec<GameMapObjectiveConfig> {
+        self.objectives
+            .iter()
+            .map(|objective| objective.config.clone())
+            .collect()
+    }
+}
+
+pub fn update_objectives(
+    mut objective_info: ResMut<ObjectiveInfo>,
+    game_lib: Res<GameLib>,
+    player_info: Res<PlayerInfo>,
+    spawner_info: Res<SpawnerInfo>,
+    game_obj_lib: Res<GameObjInfoLib>,
+    time: Res<Time>,
+    mut map_outcome: MessageWriter<MapOutcome>,
+) {
+    if objective_info.is_empty() {
+        return;
+    }
+
+    let player_teams = get_player_teams(player_info.as_ref(), game_obj_lib.as_ref());
+    let mut lost = false;
+
+    for (index, objective) in objective_info.objectives.iter_mut().enumerate() {
+        let completed = objective.update(
+            &player_teams,
+            player_info.as_ref(),
+            spawner_info.as_ref(),
+            game_lib.as_ref(),
+            game_obj_lib.as_ref(),
+            time.as_ref(),
+        );
+
+        if let GameMapObjectiveConfig::Protect { obj_name } = &objective.config {
+            if !completed {
+                info!("Protected object {} was destroyed", obj_name);
+                lost = true;
+            }
+        } else if completed && !objective.completed {
+            info!("Objective {} completed", index);
+        }
+        objective.completed = completed;
+    }
+
+    if lost {
+        map_outcome.write(MapOutcome::Lost);
+    } else if objective_info.can_be_won()
+        && objective_info
+            .objectives
+            .iter()
+            .filter(|o| !o.is_lose_only())
+            .all(|o| o.completed)
+    {
+        map_outcome.write(MapOutcome::Won);
+    }
+}
@@ -0,0 +1,385 @@
+use crate::game_map::*;
+
+use bevy::prelude::*;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Nav cells per map cell along each axis. Tanks are nearly as wide as the
+/// corridors they drive through, so they have to line up between map cell
+/// centers to fit.
+pub const NAV_CELLS_PER_MAP_CELL: usize = 4;
+
+// Clearance is only tracked up to `max_clearance`, the largest body that
+// will ask whether it fits, so an obstacle only touches the cells around it
+#[derive(Resource, Clone)]
+pub struct NavGrid {
+    pub cell_size: f32,
+    max_clearance: f32,
+    clearance: Vec<Vec<f32>>,
+}
+
+impl NavGrid {
+    pub fn new(cell_size: f32, row_count: usize, col_count: usize, max_clearance: f32) -> Self {
+        let width = col_count as f32 * cell_size;
+        let height = row_count as f32 * cell_size;
+        let mut clearance = vec![vec![0.0; col_count]; row_count];
+
+        for (row, cells) in clearance.iter_mut().enumerate() {
+            for (col, c) in cells.iter_mut().enumerate() {
+                let center = Self::center_of(cell_size, row, col);
+                *c = center
+                    .x
+                    .min(width - center.x)
+                    .min(center.y)
+                    .min(height - center.y)
+                    .min(max_clearance);
+            }
+        }
+
+        Self {
+            cell_size,
+            max_clearance,
+            clearance,
+        }
+    }
+
+    /// Covers the map with `NAV_CELLS_PER_MAP_CELL` nav cells per map cell
+    /// along each axis.
+    pub fn from_map(map: &GameMap, max_clearance: f32) -> Self {
+        Self::new(
+            map.cell_size / NAV_CELLS_PER_MAP_CELL as f32,
+            map.row_count() * NAV_CELLS_PER_MAP_CELL,
+            map.col_count() * NAV_CELLS_PER_MAP_CELL,
+            max_clearance,
+        )
+    }
+
+    #[inline]
+    pub fn row_count(&self) -> usize {
+        self.clearance.len()
+    }
+
+    #[inline]
+    pub fn col_count(&self) -> usize {
+        self.clearance[0].len()
+    }
+
+    #[inline]
+    pub fn cell_center(&self, pos: &MapPos) -> Vec2 {
+        Self::center_of(self.cell_size, pos.row, pos.col)
+    }
+
+    #[inline]
+    pub fn get_map_pos(&self, pos: &Vec2) -> MapPos {
+        let row = (pos.y / self.cell_size).floor() as i32;
+        let col = (pos.x / self.cell_size).floor() as i32;
+        MapPos {
+            row: row.clamp(0, self.row_count() as i32 - 1) as usize,
+            col: col.clamp(0, self.col_count() as i32 - 1) as usize,
+        }
+    }
+
+    #[inline]
+    pub fn clearance(&self, pos: &MapPos) -> f32 {
+        self.clearance[pos.row][pos.col]
+    }
+
+    #[inline]
+    pub fn is_passable(&self, pos: &MapPos, collide_span: f32) -> bool {
+        self.clearance(pos) >= collide_span
+    }
+
+    /// Lists the passable cells next to `pos`. A body resting against an
+    /// obstacle sits between cell centers, so the cell holding it can be
+    /// blocked while one next to it is not.
+    pub fn get_passable_around(&self, pos: &Vec2, collide_span: f32) -> Vec<MapPos> {
+        let center = self.get_map_pos(pos);
+        let rows = center.row.saturating_sub(1)..=(center.row + 1).min(self.row_count() - 1);
+        let cols = center.col.saturating_sub(1)..=(center.col + 1).min(self.col_count() - 1);
+
+        rows.flat_map(|row| cols.clone().map(move |col| MapPos { row, col }))
+            .filter(|map_pos| self.is_passable(map_pos, collide_span))
+            .collect()
+    }
+
+    /// Lists the cells a body of `collide_span` fits in, nearest to `pos`
+    /// first.
+    pub fn get_passable_by_distance(&self, pos: &Vec2, collide_span: f32) -> Vec<MapPos> {
+        let mut cells: Vec<MapPos> = (0..self.row_count())
+            .flat_map(|row| (0..self.col_count()).map(move |col| MapPos { row, col }))
+            .filter(|map_pos| self.is_passable(map_pos, collide_span))
+            .collect();
+        cells.sort_by(|a, b| {
+            let distance = |map_pos: &MapPos| self.cell_center(map_pos).distance_squared(*pos);
+            distance(a).total_cmp(&distance(b))
+        });
+        cells
+    }
+
+    pub fn add_obstacle(&mut self, pos: &Vec2, collide_span: f32) {
+        let reach = Vec2::splat(collide_span + self.max_clearance);
+        let min = self.get_map_pos(&(pos - reach));
+        let max = self.get_map_pos(&(pos + reach));
+
+        for row in min.row..=max.row {
+            for col in min.col..=max.col {
+                let d = Self::center_of(self.cell_size, row, col) - pos;
+                let gap = d.x.abs().max(d.y.abs()) - collide_span;
+                if gap < self.clearance[row][col] {
+                    self.clearance[row][col] = gap;
+                }
+            }
+        }
+    }
+
+    pub fn find_path(
+        &self,
+        start: &MapPos,
+        goal: &MapPos,
+        collide_span: f32,
+    ) -> Option<Vec<MapPos>> {
+        let col_count = self.col_count();
+        let index = |pos: &MapPos| pos.row * col_count + pos.col;
+        let heuristic =
+            |pos: &MapPos| (pos.row.abs_diff(goal.row) + pos.col.abs_diff(goal.col)) as u32;
+
+        let cell_count = self.row_count() * col_count;
+        let mut cost = vec![u32::MAX; cell_count];
+        let mut came_from: Vec<Option<MapPos>> = vec![None; cell_count];
+        let mut open = BinaryHeap::new();
+
+        cost[index(start)] = 0;
+        open.push(Reverse((heuristic(start), 0, start.row, start.col)));
+
+        while let Some(Reverse((_, g, row, col))) = open.pop() {
+            let current = MapPos { row, col };
+            if current == *goal {
+                return Some(self.build_path(&came_from, current));
+            }
+            if g > cost[index(&current)] {
+                continue;
+            }
+
+            for next in self.neighbors(&current) {
+                if next != *goal && !self.is_passable(&next, collide_span) {
+                    continue;
+                }
+
+                let next_cost = g + 1;
+                if next_cost < cost[index(&next)] {
+                    cost[index(&next)] = next_cost;
+                    came_from[index(&next)] = Some(current);
+                    open.push(Reverse((
+                        next_cost + heuristic(&next),
+                        next_cost,
+                        next.row,
+                        next.col,
+                    )));
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Marks every cell a body of `collide_span` can reach from the starts.
+    pub fn get_reachable(&self, starts: &[MapPos], collide_span: f32) -> Vec<Vec<bool>> {
+        let mut reached = vec![vec![false; self.col_count()]; self.row_count()];
+        let mut open: Vec<MapPos> = starts.to_vec();
+        for start in starts.iter() {
+            reached[start.row][start.col] = true;
+        }
+
+        while let Some(current) = open.pop() {
+            for next in self.neighbors(&current) {
+                if reached[next.row][next.col] || !self.is_passable(&next, collide_span) {
+                    continue;
+                }
+                reached[next.row][next.col] = true;
+                open.push(next);
+            }
+        }
+
+        reached
+    }
+
+    fn build_path(&self, came_from: &[Option<MapPos>], goal: MapPos) -> Vec<MapPos> {
+        let mut path = vec![goal];
+        let mut current = goal;
+
+        while let Some(prev) = came_from[current.row * self.col_count() + current.col] {
+            path.push(prev);
+            current = prev;
+        }
+
+        path.reverse();
+        path
+    }
+
+    fn neighbors(&self, pos: &MapPos) -> impl Iterator<Item = MapPos> + use<> {
+        let row = pos.row as i32;
+        let col = pos.col as i32;
+        let row_count = self.row_count() as i32;
+        let col_count = self.col_count() as i32;
+
+        [
+            (row + 1, col),
+            (row - 1, col),
+            (row, col + 1),
+            (row, col - 1),
+        ]
+        .into_iter()
+        .filter(move |(r, c)| *r >= 0 && *r < row_count && *c >= 0 && *c < col_count)
+        .map(|(r, c)| MapPos {
+            row: r as usize,
+            col: c as usize,
+        })
+    }
+
+    #[inline]
+    fn center_of(cell_size: f32, row: usize, col: usize) -> Vec2 {
+        Vec2::new(
+            (col as f32 + 0.5) * cell_size,
+            (row as f32 + 0.5) * cell_size,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CELL_SIZE: f32 = 10.0;
+
+    // The sizes in assets/game_config.json
+    const MAP_CELL_SIZE: f32 = 40.0;
+    const TILE_COLLIDE_SPAN: f32 = 20.0;
+    const TANK_COLLIDE_SPAN: f32 = 30.0;
+
+    // A 7x9 grid split by a wall down column 4, open at the given rows
+    fn walled_grid(open_rows: &[usize]) -> NavGrid {
+        let mut nav_grid = NavGrid::new(CELL_SIZE, 7, 9, CELL_SIZE);
+        for row in (0..7).filter(|row| !open_rows.contains(row)) {
+            let center = nav_grid.cell_center(&MapPos { row, col: 4 });
+            nav_grid.add_obstacle(&center, CELL_SIZE / 2.0);
+        }
+        nav_grid
+    }
+
+    // A 7x9 map split by tiles down column 4, open at the given rows
+    fn walled_map_grid(open_rows: &[usize]) -> NavGrid {
+        let map = GameMap::new(MAP_CELL_SIZE, 7, 9);
+        let mut nav_grid = NavGrid::from_map(&map, TANK_COLLIDE_SPAN);
+        for row in (0..7).filter(|row| !open_rows.contains(row)) {
+            let center = Vec2::new(4.5, row as f32 + 0.5) * MAP_CELL_SIZE;
+            nav_grid.add_obstacle(&center, TILE_COLLIDE_SPAN);
+        }
+        nav_grid
+    }
+
+    fn assert_valid_path(nav_grid: &NavGrid, path: &[MapPos], collide_span: f32) {
+        for step in path.windows(2) {
+            let distance = step[0].row.abs_diff(step[1].row) + step[0].col.abs_diff(step[1].col);
+            assert_eq!(
+                distance, 1,
+                "{:?} and {:?} are not adjacent",
+                step[0], step[1]
+            );
+        }
+        for pos in path.iter() {
+            assert!(
+                nav_grid.is_passable(pos, collide_span),
+                "{:?} is blocked",
+                pos
+            );
+        }
+    }
+
+    #[test]
+    fn find_path_goes_around_obstacle() {
+        let nav_grid = walled_grid(&[3]);
+        let start = MapPos { row: 1, col: 1 };
+        let goal = MapPos { row: 1, col: 7 };
+
+        let path = nav_grid.find_path(&start, &goal, 4.0).unwrap();
+        assert_eq!(path.first(), Some(&start));
+        assert_eq!(path.last(), Some(&goal));
+        assert!(path.contains(&MapPos { row: 3, col: 4 }));
+        assert_valid_path(&nav_grid, &path, 4.0);
+    }
+
+    #[test]
+    fn find_path_skips_gap_narrower_than_clearance() {
+        let nav_grid = walled_grid(&[3]);
+        let start = MapPos { row: 3, col: 1 };
+        let goal = MapPos { row: 3, col: 7 };
+
+        assert!(nav_grid.find_path(&start, &goal, 4.0).is_some());
+        assert_eq!(nav_grid.find_path(&start, &goal, 6.0), None);
+    }
+
+    #[test]
+    fn find_path_returns_none_for_unreachable_goal() {
+        let nav_grid = walled_grid(&[]);
+        let start = MapPos { row: 3, col: 1 };
+        let goal = MapPos { row: 3, col: 7 };
+
+        assert_eq!(nav_grid.find_path(&start, &goal, 4.0), None);
+    }
+
+    #[test]
+    fn find_path_to_start_is_single_cell() {
+        let nav_grid = walled_grid(&[3]);
+        let start = MapPos { row: 2, col: 2 };
+
+        assert_eq!(nav_grid.find_path(&start, &start, 4.0), Some(vec![start]));
+    }
+
+    #[test]
+    fn find_path_fits_tank_through_two_cell_corridor() {
+        let nav_grid = walled_map_grid(&[2, 3]);
+        let start = nav_grid.get_map_pos(&Vec2::new(60.0, 120.0));
+        let goal = nav_grid.get_map_pos(&Vec2::new(300.0, 120.0));
+
+        let path = nav_grid
+            .find_path(&start, &goal, TANK_COLLIDE_SPAN)
+            .unwrap();
+        assert_valid_path(&nav_grid, &path, TANK_COLLIDE_SPAN);
+
+        let nav_grid = walled_map_grid(&[3]);
+        assert_eq!(nav_grid.find_path(&start, &goal, TANK_COLLIDE_SPAN), None);
+    }
+
+    #[test]
+    fn find_path_runs_along_border_and_walls() {
+        let nav_grid = walled_map_grid(&[]);
+
+        // Resting against the left border, the bottom border, the left side
+        // of the wall and the top border
+        let resting = [
+            Vec2::new(30.0, 140.0),
+            Vec2::new(100.0, 30.0),
+            Vec2::new(130.0, 140.0),
+            Vec2::new(100.0, 250.0),
+        ];
+        for pos in resting.iter() {
+            assert!(
+                !nav_grid
+                    .get_passable_around(pos, TANK_COLLIDE_SPAN)
+                    .is_empty(),
+                "{:?} is blocked",
+                pos
+            );
+        }
+
+        let start = nav_grid.get_map_pos(&Vec2::new(35.0, 35.0));
+        let goal = nav_grid.get_map_pos(&Vec2::new(125.0, 245.0));
+        assert!(nav_grid.is_passable(&start, TANK_COLLIDE_SPAN));
+        assert!(nav_grid.is_passable(&goal, TANK_COLLIDE_SPAN));
+
+        let path = nav_grid
+            .find_path(&start, &goal, TANK_COLLIDE_SPAN)
+            .unwrap();
+        assert_valid_path(&nav_grid, &path, TANK_COLLIDE_SPAN);
+    }
+}
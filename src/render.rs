@@ -0,0 +1,326 @@
+use crate::game_lib::*;
+use crate::game_obj::*;
+use crate::game_state::*;
+use crate::systems::*;
+use crate::utils::*;
+
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+#[derive(Resource)]
+pub struct GameAssets {
+    origin: Vec2,
+    images: HashMap<String, Handle<Image>>,
+    texture_atlas_layout_map: HashMap<String, Handle<TextureAtlasLayout>>,
+}
+
+pub struct PresentationPlugin;
+
+impl Plugin for PresentationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_presentation.after(setup_game))
+            .add_systems(
+                PostUpdate,
+                (
+                    spawn_obj_sprites,
+                    spawn_explosion_sprites,
+                    sync_obj_transforms,
+                    animate_explosions,
+                    fade_phasing_objs,
+                    blink_invulnerable_objs,
+                    swap_damaged_images,
+                ),
+            );
+
+        for state in [
+            GameState::Title,
+            GameState::Paused,
+            GameState::GameOver,
+            GameState::Victory,
+        ] {
+            app.add_systems(OnEnter(state), spawn_state_banner);
+        }
+    }
+}
+
+impl GameAssets {
+    pub fn new(
+        game_lib: &GameLib,
+        asset_server: &AssetServer,
+        texture_atlas_layouts: &mut Assets<TextureAtlasLayout>,
+    ) -> Self {
+        let config = game_lib.get_game_config();
+        let mut game_assets = Self {
+            origin: Vec2::new(-config.window_width() / 2.0, -config.window_height() / 2.0),
+            images: HashMap::new(),
+            texture_atlas_layout_map: HashMap::new(),
+        };
+
+        game_assets.load_images(game_lib, asset_server);
+        game_assets.load_texture_layouts(game_lib, texture_atlas_layouts);
+
+        game_assets
+    }
+
+    #[inline]
+    pub fn get_screen_pos(&self, pos: &Vec2) -> Vec2 {
+        self.origin + pos
+    }
+
+    #[inline]
+    pub fn get_image(&self, name: &String) -> Option<Handle<Image>> {
+        match self.images.get(name) {
+            Some(image) => Some(image.clone()),
+            None => {
+                error!("Failed to find image {}", name);
+                None
+            }
+        }
+    }
+
+    #[inline]
+    pub fn get_texture_atlas_layout(
+        &self,
+        explosion_name: &String,
+    ) -> Option<Handle<TextureAtlasLayout>> {
+        match self.texture_atlas_layout_map.get(explosion_name) {
+            Some(layout) => Some(layout.clone()),
+            None => {
+                error!("Failed to find TextureAtlasLayout {}", explosion_name);
+                None
+            }
+        }
+    }
+
+    fn load_images(&mut self, game_lib: &GameLib, asset_server: &AssetServer) {
+        for (name, file_path) in game_lib.get_game_config().image_files().iter() {
+            self.images
+                .insert(name.clone(), asset_server.load(file_path));
+        }
+    }
+
+    fn load_texture_layouts(
+        &mut self,
+        game_lib: &GameLib,
+        texture_atlas_layouts: &mut Assets<TextureAtlasLayout>,
+    ) {
+        for (explosion_name, explosion_config) in
+            game_lib.get_game_config().explosion_configs.iter()
+        {
+            let tile_size = UVec2 {
+                x: explosion_config.size[0],
+                y: explosion_config.size[1],
+            };
+            let layout = texture_atlas_layouts.add(TextureAtlasLayout::from_grid(
+                tile_size,
+                explosion_config.frame_count,
+                1,
+                None,
+                None,
+            ));
+            self.texture_atlas_layout_map
+                .insert(explosion_name.clone(), layout);
+        }
+    }
+}
+
+pub fn setup_presentation(
+    game_lib: If<Res<GameLib>>,
+    asset_server: Res<AssetServer>,
+    mut texture_atlas_layouts: ResMut<Assets<TextureAtlasLayout>>,
+    mut commands: Commands,
+    mut window: Single<&mut Window>,
+) {
+    let config = game_lib.get_game_config();
+    window
+        .resolution
+        .set(config.window_width(), config.window_height());
+    commands.spawn(Camera2d);
+
+    commands.insert_resource(GameAssets::new(
+        game_lib.as_ref(),
+        asset_server.as_ref(),
+        texture_atlas_layouts.as_mut(),
+    ));
+}
+
+pub fn spawn_obj_sprites(
+    obj_query: Query<
+        (Entity, &GameObjComponent, Option<&PhasingComponent>),
+        Added<GameObjComponent>,
+    >,
+    game_lib: Res<GameLib>,
+    game_assets: Res<GameAssets>,
+    game_obj_lib: Res<GameObjInfoLib>,
+    mut commands: Commands,
+) {
+    for (entity, obj_comp, phasing_comp) in obj_query.iter() {
+        // Phasing objects restored from a save are no longer in GameObjInfoLib
+        let (pos, direction) = match (game_obj_lib.get(&entity), phasing_comp) {
+            (Some(obj), _) => (obj.pos, obj.direction),
+            (None, Some(phasing_comp)) => (phasing_comp.pos, phasing_comp.direction),
+            (None, None) => continue,
+        };
+        let obj_config = game_lib.get_obj_config(obj_comp.config_index);
+        let hp = game_obj_lib.get(&entity).and_then(|obj| obj.hp);
+        let Some(image) = game_assets.get_image(obj_config.get_image(hp)) else {
+            continue;
+        };
+        let size = arr_to_vec2(&obj_config.size);
+        let screen_pos = game_assets.get_screen_pos(&pos);
+
+        commands.entity(entity).insert((
+            Sprite {
+                image,
+                custom_size: Some(size),
+                image_mode: SpriteImageMode::Scale(ScalingMode::FillCenter),
+                ..default()
+            },
+            Transform {
+                translation: Vec3::new(screen_pos.x, screen_pos.y, obj_config.z),
+                rotation: get_rotation(&direction),
+                ..default()
+            },
+        ));
+    }
+}
+
+pub fn spawn_explosion_sprites(
+    explosion_query: Query<(Entity, &ExplosionComponent), Added<ExplosionComponent>>,
+    game_lib: Res<GameLib>,
+    game_assets: Res<GameAssets>,
+    mut commands: Commands,
+) {
+    for (entity, explosion_comp) in explosion_query.iter() {
+        let Some(explosion_config) = game_lib.get_explosion_config(&explosion_comp.explosion_name)
+        else {
+            continue;
+        };
+        let Some(texture) = game_assets.get_image(&explosion_config.image) else {
+            continue;
+        };
+        let Some(layout) = game_assets.get_texture_atlas_layout(&explosion_comp.explosion_name)
+        else {
+            continue;
+        };
+        let screen_pos = game_assets.get_screen_pos(&explosion_comp.pos);
+
+        commands.entity(entity).insert((
+            Sprite::from_atlas_image(
+                texture,
+                TextureAtlas {
+                    layout,
+                    index: explosion_comp.index,
+                },
+            ),
+            Transform::from_xyz(screen_pos.x, screen_pos.y, explosion_config.z),
+        ));
+    }
+}
+
+pub fn sync_obj_transforms(
+    mut obj_query: Query<(Entity, &mut Transform), With<GameObjComponent>>,
+    game_assets: Res<GameAssets>,
+    game_obj_lib: Res<GameObjInfoLib>,
+) {
+    for (entity, mut transform) in obj_query.iter_mut() {
+        let Some(obj) = game_obj_lib.get(&entity) else {
+            continue;
+        };
+        let screen_pos = game_assets.get_screen_pos(&obj.pos);
+
+        transform.translation.x = screen_pos.x;
+        transform.translation.y = screen_pos.y;
+        transform.rotation = get_rotation(&obj.direction);
+    }
+}
+
+pub fn swap_damaged_images(
+    mut obj_query: Query<(Entity, &GameObjComponent, &mut Sprite)>,
+    game_lib: Res<GameLib>,
+    game_assets: Res<GameAssets>,
+    game_obj_lib: Res<GameObjInfoLib>,
+) {
+    if !game_obj_lib.is_changed() {
+        return;
+    }
+
+    for (entity, obj_comp, mut sprite) in obj_query.iter_mut() {
+        let obj_config = game_lib.get_obj_config(obj_comp.config_index);
+        if obj_config.damaged_images.is_empty() {
+            continue;
+        }
+        let Some(obj) = game_obj_lib.get(&entity) else {
+            continue;
+        };
+        let Some(image) = game_assets.get_image(obj_config.get_image(obj.hp)) else {
+            continue;
+        };
+
+        if sprite.image != image {
+            sprite.image = image;
+        }
+    }
+}
+
+pub fn animate_explosions(mut explosion_query: Query<(&mut Sprite, &ExplosionComponent)>) {
+    for (mut sprite, explosion_comp) in explosion_query.iter_mut() {
+        if let Some(atlas) = sprite.texture_atlas.as_mut() {
+            atlas.index = explosion_comp.index;
+        }
+    }
+}
+
+pub fn fade_phasing_objs(mut phasing_obj_query: Query<(&mut Sprite, &PhasingComponent)>) {
+    for (mut sprite, phasing_comp) in phasing_obj_query.iter_mut() {
+        sprite
+            .color
+            .set_alpha(phasing_comp.timer.fraction_remaining());
+    }
+}
+
+pub fn blink_invulnerable_objs(
+    mut invulnerable_query: Query<(&mut Sprite, &InvulnerableComponent)>,
+    mut removed: RemovedComponents<InvulnerableComponent>,
+    mut sprite_query: Query<&mut Sprite, Without<InvulnerableComponent>>,
+) {
+    for (mut sprite, invulnerable_comp) in invulnerable_query.iter_mut() {
+        let visible = ((invulnerable_comp.elapsed_secs() * 8.0) as u32).is_multiple_of(2);
+        sprite.color.set_alpha(if visible { 1.0 } else { 0.3 });
+    }
+
+    for entity in removed.read() {
+        if let Ok(mut sprite) = sprite_query.get_mut(entity) {
+            sprite.color.set_alpha(1.0);
+        }
+    }
+}
+
+pub fn spawn_state_banner(state: Res<State<GameState>>, mut commands: Commands) {
+    let text = match state.get() {
+        GameState::Title => "Press Enter to start",
+        GameState::Paused => "Paused",
+        GameState::GameOver => "Game over\nPress Enter to restart",
+        GameState::Victory => "Victory\nPress Enter to play again",
+        GameState::Playing => return,
+    };
+
+    commands.spawn((
+        Node {
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        DespawnOnExit(*state.get()),
+        children![(
+            Text::new(text),
+            TextFont {
+                font_size: 48.0,
+                ..default()
+            },
+            TextLayout::new_with_justify(Justify::Center),
+        )],
+    ));
+}
@@ -0,0 +1,163 @@
+use crate::game_obj::*;
+use crate::game_state::*;
+use crate::input::*;
+use crate::my_error::*;
+use crate::simulation::*;
+use crate::utils::*;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+#[derive(Resource, Clone, Serialize, Deserialize)]
+pub struct ReplayHeader {
+    pub config_path: PathBuf,
+    pub map_path: PathBuf,
+    pub seed: u64,
+    #[serde(default)]
+    pub mode: GameMode,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Replay {
+    #[serde(flatten)]
+    pub header: ReplayHeader,
+    pub inputs: Vec<PlayerInputs>,
+    // Number of inputs played each time the game was restarted after it ended
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub restarts: Vec<usize>,
+}
+
+#[derive(Resource)]
+pub struct ReplayRecorder {
+    pub path: PathBuf,
+    pub replay: Replay,
+}
+
+#[derive(Resource)]
+pub struct ReplayPlayer {
+    pub inputs: Vec<PlayerInputs>,
+    pub next_index: usize,
+    pub restarts: Vec<usize>,
+}
+
+impl ReplayPlayer {
+    #[inline]
+    pub fn is_restart_due(&self) -> bool {
+        self.restarts.first() == Some(&self.next_index)
+    }
+
+    pub fn take_restart(&mut self) -> bool {
+        let is_due = self.is_restart_due();
+        if is_due {
+            self.restarts.remove(0);
+        }
+        is_due
+    }
+}
+
+impl Replay {
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), MyError> {
+        let file = File::create(path)?;
+        serde_json::to_writer(BufWriter::new(file), self)?;
+        Ok(())
+    }
+}
+
+pub fn setup_replay(args: &Args, commands: &mut Commands) -> Result<ReplayHeader, MyError> {
+    if let Some(replay_path) = args.replay_path.as_ref() {
+        let replay: Replay = read_json(replay_path)?;
+        info!(
+            "Playing {} ticks from replay {:?}",
+            replay.inputs.len(),
+            replay_path
+        );
+
+        let header = replay.header.clone();
+        commands.insert_resource(ReplayPlayer {
+            inputs: replay.inputs,
+            next_index: 0,
+            restarts: replay.restarts,
+        });
+        return Ok(header);
+    }
+
+    let (Some(config_path), Some(map_path)) = (args.config_path.clone(), args.map_path.clone())
+    else {
+        return Err(MyError::Args(
+            "config_path and map_path are required unless replaying".to_string(),
+        ));
+    };
+
+    let header = ReplayHeader {
+        config_path,
+        map_path,
+        seed: args.seed.unwrap_or_else(rand::random),
+        mode: args.mode,
+    };
+
+    if let Some(record_path) = args.record_path.as_ref() {
+        commands.insert_resource(ReplayRecorder {
+            path: record_path.clone(),
+            replay: Replay {
+                header: header.clone(),
+                inputs: Vec::new(),
+                restarts: Vec::new(),
+            },
+        });
+    }
+
+    Ok(header)
+}
+
+pub fn play_replay_input(
+    mut replay_player: ResMut<ReplayPlayer>,
+    mut player_inputs: ResMut<PlayerInputs>,
+    sim_tick: Res<SimTick>,
+    game_obj_lib: Res<GameObjInfoLib>,
+    mut exit_app: MessageWriter<AppExit>,
+) {
+    let Some(inputs) = replay_player.inputs.get(replay_player.next_index).cloned() else {
+        player_inputs.clear();
+        if replay_player.next_index == replay_player.inputs.len() {
+            info!(
+                "Replay finished after {} ticks, state digest {:016x}",
+                sim_tick.0,
+                get_state_digest(game_obj_lib.as_ref())
+            );
+            replay_player.next_index += 1;
+            exit_app.write(AppExit::Success);
+        }
+        return;
+    };
+
+    *player_inputs = inputs;
+    replay_player.next_index += 1;
+}
+
+pub fn record_input(mut recorder: ResMut<ReplayRecorder>, player_inputs: Res<PlayerInputs>) {
+    recorder.replay.inputs.push(player_inputs.clone());
+}
+
+pub fn save_replay(
+    recorder: Res<ReplayRecorder>,
+    game_obj_lib: Res<GameObjInfoLib>,
+    mut exit_events: MessageReader<AppExit>,
+) {
+    if exit_events.is_empty() {
+        return;
+    }
+    exit_events.clear();
+
+    match recorder.replay.save(&recorder.path) {
+        Ok(()) => info!(
+            "Saved {} ticks of input to {:?}, state digest {:016x}",
+            recorder.replay.inputs.len(),
+            recorder.path,
+            get_state_digest(game_obj_lib.as_ref())
+        ),
+        Err(err) => error!("Failed to save replay to {:?}: {}", recorder.path, err),
+    }
+}
@@ -0,0 +1,188 @@
+use crate::ai::*;
+use crate::game_lib::*;
+use crate::game_map::*;
+use crate::game_obj::*;
+use crate::game_state::*;
+use crate::my_error::*;
+use crate::objective::*;
+use crate::simulation::*;
+use crate::spawner::*;
+use crate::systems::*;
+use crate::utils::*;
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+type SavedObjData = (
+    Entity,
+    &'static GameObjComponent,
+    Option<&'static ShootComponent>,
+    Option<&'static AIComponent>,
+    Option<&'static PhasingComponent>,
+    Option<&'static PlayerComponent>,
+    Option<&'static InvulnerableComponent>,
+    Option<&'static Name>,
+);
+
+// Everything a saved game is read from
+#[derive(SystemParam)]
+pub struct SaveParams<'w, 's> {
+    game_lib: Res<'w, GameLib>,
+    game_obj_lib: Res<'w, GameObjInfoLib>,
+    player_info: Res<'w, PlayerInfo>,
+    spawner_info: Res<'w, SpawnerInfo>,
+    objective_info: Res<'w, ObjectiveInfo>,
+    map: Res<'w, GameMap>,
+    sim_tick: Res<'w, SimTick>,
+    rng: Res<'w, GameRng>,
+    obj_query: Query<'w, 's, SavedObjData>,
+    explosion_query: Query<'w, 's, (Entity, &'static ExplosionComponent)>,
+}
+
+impl SaveParams<'_, '_> {
+    pub fn get_save_config(&self) -> GameMapConfig {
+        let game_lib = self.game_lib.as_ref();
+        let game_obj_lib = self.game_obj_lib.as_ref();
+        let player_info = self.player_info.as_ref();
+        let spawner_info = self.spawner_info.as_ref();
+
+        let mut objs: Vec<_> = self.obj_query.iter().collect();
+        objs.sort_by_key(|(entity, ..)| *entity);
+        let mut explosions: Vec<_> = self.explosion_query.iter().collect();
+        explosions.sort_by_key(|(entity, _)| *entity);
+
+        let mut map_config = GameMapConfig {
+            grid: None,
+            objs: Vec::new(),
+            spawn_points: self.map.spawn_points.clone(),
+            spawners: Vec::new(),
+            waves: Vec::new(),
+            current_wave: None,
+            objectives: self.objective_info.get_save_configs(),
+            explosions: Vec::new(),
+            phasing_objs: Vec::new(),
+            sim_tick: Some(self.sim_tick.0),
+            seed: Some(self.rng.seed),
+        };
+
+        for (
+            entity,
+            obj_comp,
+            shoot_comp,
+            ai_comp,
+            phasing_comp,
+            player_comp,
+            invulnerable_comp,
+            name,
+        ) in objs.iter()
+        {
+            let config_name = game_lib.get_obj_config(obj_comp.config_index).name.clone();
+
+            if let Some(phasing_comp) = phasing_comp {
+                map_config.phasing_objs.push(GameMapPhasingObjConfig {
+                    config_name,
+                    pos: phasing_comp.pos.to_array(),
+                    direction: phasing_comp.direction.into(),
+                    elapsed: phasing_comp.timer.elapsed_secs(),
+                    player_id: player_comp.map(|c| c.player_id),
+                    team: (phasing_comp.team != game_lib.get_obj_team(obj_comp.config_index))
+                        .then(|| game_lib.get_team_name(phasing_comp.team).clone()),
+                });
+            } else if let Some(obj) = game_obj_lib.get(entity) {
+                map_config.objs.push(GameMapObjConfig {
+                    name: name.map(|n| n.to_string()),
+                    player_id: player_comp.map(|c| c.player_id),
+                    team: (obj.team != game_lib.get_obj_team(obj_comp.config_index))
+                        .then(|| game_lib.get_team_name(obj.team).clone()),
+                    hp: obj.hp,
+                    shoot_elapsed: shoot_comp.map(|c| c.timer.elapsed_secs()),
+                    lives: player_comp
+                        .and_then(|c| player_info.get(&c.player_id))
+                        .map(|slot| slot.lives),
+                    invulnerable_elapsed: invulnerable_comp.map(|c| c.elapsed_secs()),
+                    spawner: spawner_info.get_spawner_index(entity),
+                    ai: ai_comp.map(|c| c.get_save_config()),
+                    ..GameMapObjConfig::new(config_name, obj.pos.to_array(), obj.direction.into())
+                });
+            }
+        }
+
+        for (player_id, slot) in player_info.iter() {
+            let Some(respawn_timer) = slot.respawn_timer.as_ref() else {
+                continue;
+            };
+            map_config.objs.push(GameMapObjConfig {
+                player_id: Some(*player_id),
+                team: (slot.team != game_lib.get_obj_team(slot.config_index))
+                    .then(|| game_lib.get_team_name(slot.team).clone()),
+                lives: Some(slot.lives),
+                respawn_elapsed: Some(respawn_timer.elapsed_secs()),
+                ..GameMapObjConfig::new(
+                    game_lib.get_obj_config(slot.config_index).name.clone(),
+                    slot.spawn_pos.to_array(),
+                    slot.spawn_direction.into(),
+                )
+            });
+        }
+
+        spawner_info.get_save_config(game_lib, &mut map_config);
+
+        for (_, explosion_comp) in explosions.iter() {
+            map_config.explosions.push(GameMapExplosionConfig {
+                explosion_name: explosion_comp.explosion_name.clone(),
+                pos: explosion_comp.pos.to_array(),
+                index: explosion_comp.index,
+                elapsed: explosion_comp.timer.elapsed_secs(),
+            });
+        }
+
+        map_config
+    }
+}
+
+pub fn quick_save(keys: Res<ButtonInput<KeyCode>>, args: Res<Args>, save_params: SaveParams) {
+    let quick_save_keys = &save_params
+        .game_lib
+        .get_game_config()
+        .key_bindings
+        .quick_save;
+    if !keys.any_just_pressed(quick_save_keys.iter().copied()) {
+        return;
+    }
+
+    let map_config = save_params.get_save_config();
+    match save_game(&args.save_path, &map_config) {
+        Ok(()) => info!("Saved game to {:?}", args.save_path),
+        Err(err) => error!("Failed to save game to {:?}: {}", args.save_path, err),
+    }
+}
+
+pub fn quick_load(
+    keys: Res<ButtonInput<KeyCode>>,
+    args: Res<Args>,
+    mode: Res<GameMode>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut reloader: GameWorldReloader,
+) {
+    let quick_load_keys = &reloader.game_lib.get_game_config().key_bindings.quick_load;
+    if !keys.any_just_pressed(quick_load_keys.iter().copied()) {
+        return;
+    }
+
+    if let Err(err) = reloader.reload(&args.save_path, *mode) {
+        error!("Failed to load game from {:?}: {}", args.save_path, err);
+        return;
+    }
+    next_state.set(GameState::Playing);
+
+    info!("Loaded game from {:?}", args.save_path);
+}
+
+pub fn save_game<P: AsRef<Path>>(path: P, map_config: &GameMapConfig) -> Result<(), MyError> {
+    let file = File::create(path)?;
+    serde_json::to_writer_pretty(BufWriter::new(file), map_config)?;
+    Ok(())
+}
@@ -0,0 +1,151 @@
+use crate::game_obj::*;
+use crate::game_state::*;
+use crate::input::*;
+use crate::objective::*;
+use crate::replay::*;
+use crate::save::*;
+use crate::spawner::*;
+use crate::systems::*;
+use crate::utils::*;
+
+use bevy::prelude::*;
+use rand::{SeedableRng, rngs::SmallRng};
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct SimTick(pub u64);
+
+// Reseeded from the seed and the tick at the start of every tick, so a saved
+// game only needs the seed to draw the same numbers again
+#[derive(Resource)]
+pub struct GameRng {
+    pub seed: u64,
+    pub rng: SmallRng,
+}
+
+pub struct SimulationPlugin;
+
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SimTick>()
+            .init_resource::<PlayerInputs>()
+            .add_message::<PlayerDied>()
+            .add_message::<BaseDestroyed>()
+            .add_message::<MapOutcome>()
+            .add_systems(Startup, setup_game)
+            .add_systems(
+                FixedUpdate,
+                (
+                    reseed_rng,
+                    read_player_input.run_if(
+                        resource_exists::<ButtonInput<KeyCode>>
+                            .and(not(resource_exists::<ReplayPlayer>)),
+                    ),
+                    play_replay_input.run_if(resource_exists::<ReplayPlayer>),
+                    record_input.run_if(resource_exists::<ReplayRecorder>),
+                    apply_player_input,
+                    update_ai,
+                    update_missiles,
+                    update_explosions,
+                    update_phasing_objs,
+                    update_spawners,
+                    cleanup,
+                    update_nav_grid,
+                    detect_player_deaths,
+                    detect_base_deaths,
+                    handle_player_deaths,
+                    respawn_players,
+                    update_invulnerability,
+                    update_objectives,
+                    check_game_result,
+                    advance_tick,
+                )
+                    .chain()
+                    .run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(
+                Update,
+                (
+                    (
+                        handle_state_keys,
+                        // Replays and recordings only follow the map they
+                        // started from
+                        (quick_save, quick_load).run_if(
+                            in_state(GameState::Playing)
+                                .or(in_state(GameState::Paused))
+                                .and(not(resource_exists::<ReplayPlayer>))
+                                .and(not(resource_exists::<ReplayRecorder>)),
+                        ),
+                    )
+                        .run_if(resource_exists::<ButtonInput<KeyCode>>),
+                    restart_game
+                        .run_if(in_state(GameState::GameOver).or(in_state(GameState::Victory))),
+                ),
+            )
+            .add_systems(OnEnter(GameState::GameOver), report_game_result)
+            .add_systems(OnEnter(GameState::Victory), report_game_result)
+            .add_systems(Last, save_replay.run_if(resource_exists::<ReplayRecorder>));
+    }
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        info!("Using RNG seed {}", seed);
+        Self {
+            seed,
+            rng: SmallRng::seed_from_u64(seed),
+        }
+    }
+}
+
+pub fn reseed_rng(sim_tick: Res<SimTick>, mut rng: ResMut<GameRng>) {
+    rng.rng = SmallRng::seed_from_u64(mix_seed(mix_seed(rng.seed) ^ sim_tick.0));
+}
+
+// SplitMix64 step, so that neighbouring seeds and ticks give unrelated
+// streams rather than the same one shifted by a tick
+fn mix_seed(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+pub fn advance_tick(
+    args: Res<Args>,
+    game_obj_lib: Res<GameObjInfoLib>,
+    mut sim_tick: ResMut<SimTick>,
+    mut exit_app: MessageWriter<AppExit>,
+) {
+    sim_tick.0 += 1;
+
+    if args
+        .max_ticks
+        .is_some_and(|max_ticks| sim_tick.0 >= max_ticks)
+    {
+        info!(
+            "Reached {} ticks, state digest {:016x}, exiting",
+            sim_tick.0,
+            get_state_digest(game_obj_lib.as_ref())
+        );
+        exit_app.write(AppExit::Success);
+    }
+}
+
+pub fn get_state_digest(game_obj_lib: &GameObjInfoLib) -> u64 {
+    let mut objs: Vec<(&Entity, &GameObjInfo)> = game_obj_lib.iter().collect();
+    objs.sort_by_key(|(entity, _)| **entity);
+
+    let mut hasher = DefaultHasher::new();
+    for (entity, obj) in objs {
+        entity.hash(&mut hasher);
+        obj.config_index.hash(&mut hasher);
+        obj.pos.x.to_bits().hash(&mut hasher);
+        obj.pos.y.to_bits().hash(&mut hasher);
+        obj.direction.x.to_bits().hash(&mut hasher);
+        obj.direction.y.to_bits().hash(&mut hasher);
+        obj.hp.map(f32::to_bits).hash(&mut hasher);
+    }
+
+    hasher.finish()
+}
@@ -0,0 +1,422 @@
+use crate::game_lib::*;
+use crate::game_map::*;
+use crate::game_obj::*;
+use crate::utils::*;
+
+use bevy::ecs::entity::EntityHashSet;
+use bevy::prelude::*;
+use std::time::Duration;
+
+pub struct Spawner {
+    pub config_name: String,
+    pub config_index: Option<usize>,
+    pub pos: Vec2,
+    pub direction: Vec2,
+    pub count: u32,
+    pub max_alive: u32,
+    pub team: Option<usize>,
+    pub wave: Option<usize>,
+    pub spawned: u32,
+    pub timer: Timer,
+    pub alive: EntityHashSet,
+}
+
+#[derive(Resource, Default)]
+pub struct SpawnerInfo {
+    pub spawners: Vec<Spawner>,
+    pub wave_count: usize,
+    pub current_wave: usize,
+}
+
+impl Spawner {
+    fn new(config: &GameMapSpawnerConfig, wave: Option<usize>, game_lib: &GameLib) -> Self {
+        let mut config_index = game_lib.get_obj_config_index(&config.config_name);
+        if config_index.is_none() {
+            warn!(
+                "Failed to find config name {} in GameLib, spawner is disabled",
+                config.config_name
+            );
+        } else if let Some(problem) = config.get_problem() {
+            warn!(
+                "Spawner of {} {}, spawner is disabled",
+                config.config_name, problem
+            );
+            config_index = None;
+        }
+
+        let team =
+            config
+                .team
+                .as_ref()
+                .and_then(|team_name| match game_lib.get_team_index(team_name) {
+                    Some(team) => Some(team),
+                    None => {
+                        warn!("Failed to find team {} in GameLib", team_name);
+                        None
+                    }
+                });
+
+        let mut timer = Timer::from_seconds(config.interval, TimerMode::Once);
+        if let Some(elapsed) = config.elapsed {
+            timer.set_elapsed(Duration::from_secs_f32(elapsed));
+        }
+
+        Self {
+            config_name: config.config_name.clone(),
+            config_index,
+            pos: arr_to_vec2(&config.pos),
+            direction: config.direction.into(),
+            count: config.count,
+            max_alive: config.max_alive,
+            team,
+            wave,
+            spawned: config.spawned.unwrap_or_default(),
+            timer,
+            alive: EntityHashSet::new(),
+        }
+    }
+
+    #[inline]
+    pub fn is_exhausted(&self) -> bool {
+        self.config_index.is_none() || self.spawned >= self.count
+    }
+
+    #[inline]
+    pub fn is_cleared(&self) -> bool {
+        self.is_exhausted() && self.alive.is_empty()
+    }
+
+    pub fn get_save_config(&self, game_lib: &GameLib) -> GameMapSpawnerConfig {
+        GameMapSpawnerConfig {
+            config_name: self.config_name.clone(),
+            pos: self.pos.to_array(),
+            direction: self.direction.into(),
+            count: self.count,
+            interval: self.timer.duration().as_secs_f32(),
+            max_alive: self.max_alive,
+            team: self.team.map(|team| game_lib.get_team_name(team).clone()),
+            spawned: Some(self.spawned),
+            elapsed: Some(self.timer.elapsed_secs()),
+        }
+    }
+}
+
+impl SpawnerInfo {
+    pub fn from_config(map_config: &GameMapConfig, game_lib: &GameLib) -> Self {
+        let mut spawners: Vec<Spawner> = map_config
+            .spawners
+            .iter()
+            .map(|config| Spawner::new(config, None, game_lib))
+            .collect();
+
+        for (wave, wave_config) in map_config.waves.iter().enumerate() {
+            spawners.extend(
+                wave_config
+                    .spawners
+                    .iter()
+                    .map(|config| Spawner::new(config, Some(wave), game_lib)),
+            );
+        }
+
+        Self {
+            spawners,
+            wave_count: map_config.waves.len(),
+            current_wave: map_config.current_wave.unwrap_or_default(),
+        }
+    }
+
+    /// True once every spawner has spawned all its tanks, so no more enemies
+    /// can appear.
+    pub fn is_finished(&self) -> bool {
+        self.current_wave >= self.wave_count
+            && self
+                .spawners
+                .iter()
+                .filter(|spawner| spawner.wave.is_none())
+                .all(|spawner| spawner.is_exhausted())
+    }
+
+    #[inline]
+    pub fn get_spawner_index(&self, entity: &Entity) -> Option<usize> {
+        self.spawners
+            .iter()
+            .position(|spawner| spawner.alive.contains(entity))
+    }
+
+    pub fn get_save_config(&self, game_lib: &GameLib, map_config: &mut GameMapConfig) {
+        for spawner in self.spawners.iter() {
+            let config = spawner.get_save_config(game_lib);
+            match spawner.wave {
+                None => map_config.spawners.push(config),
+                Some(wave) => {
+                    if map_config.waves.len() <= wave {
+                        map_config
+                            .waves
+                            .resize_with(wave + 1, || GameMapWaveConfig {
+                                spawners: Vec::new(),
+                            });
+                    }
+                    map_config.waves[wave].spawners.push(config);
+                }
+            }
+        }
+
+        if self.current_wave > 0 {
+            map_config.current_wave = Some(self.current_wave);
+        }
+    }
+
+    fn is_active(&self, spawner: &Spawner) -> bool {
+        spawner.wave.is_none_or(|wave| wave == self.current_wave)
+    }
+
+    fn is_wave_cleared(&self) -> bool {
+        self.spawners
+            .iter()
+            .filter(|spawner| spawner.wave == Some(self.current_wave))
+            .all(|spawner| spawner.is_cleared())
+    }
+}
+
+pub fn update_spawners(
+    mut commands: Commands,
+    game_lib: Res<GameLib>,
+    mut spawner_info: ResMut<SpawnerInfo>,
+    mut map: ResMut<GameMap>,
+    mut game_obj_lib: ResMut<GameObjInfoLib>,
+    despawn_pool: Res<DespawnPool>,
+    time: Res<Time>,
+) {
+    let spawner_info = spawner_info.as_mut();
+
+    for index in 0..spawner_info.spawners.len() {
+        if !spawner_info.is_active(&spawner_info.spawners[index]) {
+            continue;
+        }
+
+        let spawner = &mut spawner_info.spawners[index];
+        spawner.alive.retain(|e| game_obj_lib.contains_key(e));
+
+        let Some(config_index) = spawner.config_index else {
+            continue;
+        };
+        if spawner.is_exhausted() || spawner.alive.len() as u32 >= spawner.max_alive {
+            continue;
+        }
+
+        spawner.timer.tick(time.delta());
+        if !spawner.timer.is_finished() {
+            continue;
+        }
+
+        // Keeps the timer finished and retries next tick until the area is free
+        let collide_span = game_lib.get_obj_config(config_index).collide_span;
+        if !map.is_area_free(
+            &spawner.pos,
+            collide_span,
+            game_obj_lib.as_ref(),
+            despawn_pool.as_ref(),
+        ) {
+            continue;
+        }
+
+        let Some(entity) = map.add_obj(
+            config_index,
+            &spawner.pos,
+            &spawner.direction,
+            game_lib.as_ref(),
+            game_obj_lib.as_mut(),
+            &mut commands,
+        ) else {
+            continue;
+        };
+
+        if let Some(team) = spawner.team
+            && let Some(obj) = game_obj_lib.get_mut(&entity)
+        {
+            obj.team = team;
+        }
+
+        spawner.alive.insert(entity);
+        spawner.spawned += 1;
+        spawner.timer.reset();
+        info!(
+            "Spawner {} spawned {} {}/{} at {:?}",
+            index, spawner.config_name, spawner.spawned, spawner.count, spawner.pos
+        );
+    }
+
+    while spawner_info.current_wave < spawner_info.wave_count && spawner_info.is_wave_cleared() {
+        spawner_info.current_wave += 1;
+        if spawner_info.current_wave < spawner_info.wave_count {
+            info!("Wave {} started", spawner_info.current_wave + 1);
+        } else {
+            info!("All waves cleared");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::entity::EntityHashMap;
+    use bevy::ecs::system::RunSystemOnce;
+
+    const CONFIG_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/game_config.json");
+
+    fn new_spawner_config(x: f32, count: u32, max_alive: u32) -> GameMapSpawnerConfig {
+        GameMapSpawnerConfig {
+            config_name: "ai_tank".to_string(),
+            pos: [x, 400.0],
+            direction: Direction::Down,
+            count,
+            interval: 1.0,
+            max_alive,
+            team: None,
+            spawned: None,
+            elapsed: None,
+        }
+    }
+
+    fn new_world(map_config: &GameMapConfig) -> World {
+        let game_lib = GameLib::new(CONFIG_PATH).unwrap();
+        let mut world = World::new();
+        world.insert_resource(SpawnerInfo::from_config(map_config, &game_lib));
+        world.insert_resource(game_lib);
+        world.insert_resource(GameMap::new(40.0, 20, 20));
+        world.insert_resource(GameObjInfoLib(EntityHashMap::default()));
+        world.insert_resource(DespawnPool(EntityHashSet::default()));
+        world.insert_resource(Time::<()>::default());
+        world
+    }
+
+    fn update(world: &mut World, seconds: f32) {
+        world
+            .resource_mut::<Time>()
+            .advance_by(Duration::from_secs_f32(seconds));
+        world.run_system_once(update_spawners).unwrap();
+    }
+
+    fn get_spawned(world: &World) -> Vec<u32> {
+        world
+            .resource::<SpawnerInfo>()
+            .spawners
+            .iter()
+            .map(|spawner| spawner.spawned)
+            .collect()
+    }
+
+    // Removes the tanks of the spawner as if they were destroyed
+    fn destroy_spawned(world: &mut World, index: usize) {
+        let alive: Vec<Entity> = world.resource::<SpawnerInfo>().spawners[index]
+            .alive
+            .iter()
+            .copied()
+            .collect();
+        for entity in alive {
+            let obj = world
+                .resource_mut::<GameObjInfoLib>()
+                .remove(&entity)
+                .unwrap();
+            world
+                .resource_mut::<GameMap>()
+                .remove_obj(&obj.map_pos, &entity);
+        }
+    }
+
+    #[test]
+    fn spawns_after_each_interval() {
+        let mut world = new_world(&GameMapConfig {
+            spawners: vec![new_spawner_config(400.0, 2, 1)],
+            ..Default::default()
+        });
+
+        update(&mut world, 0.5);
+        assert_eq!(get_spawned(&world), [0]);
+        update(&mut world, 0.5);
+        assert_eq!(get_spawned(&world), [1]);
+
+        // The interval only starts counting once a tank may be spawned again
+        update(&mut world, 2.0);
+        assert_eq!(get_spawned(&world), [1]);
+        destroy_spawned(&mut world, 0);
+        update(&mut world, 0.5);
+        assert_eq!(get_spawned(&world), [1]);
+        update(&mut world, 0.5);
+        assert_eq!(get_spawned(&world), [2]);
+    }
+
+    #[test]
+    fn waits_until_spawn_area_is_free() {
+        let mut world = new_world(&GameMapConfig {
+            spawners: vec![new_spawner_config(400.0, 2, 2)],
+            ..Default::default()
+        });
+
+        update(&mut world, 1.0);
+        update(&mut world, 1.0);
+        assert_eq!(get_spawned(&world), [1]);
+
+        destroy_spawned(&mut world, 0);
+        update(&mut world, 0.0);
+        assert_eq!(get_spawned(&world), [2]);
+    }
+
+    #[test]
+    fn next_wave_starts_once_previous_is_cleared() {
+        let mut world = new_world(&GameMapConfig {
+            spawners: vec![new_spawner_config(100.0, 1, 1)],
+            waves: vec![
+                GameMapWaveConfig {
+                    spawners: vec![new_spawner_config(400.0, 1, 1)],
+                },
+                GameMapWaveConfig {
+                    spawners: vec![new_spawner_config(700.0, 1, 1)],
+                },
+            ],
+            ..Default::default()
+        });
+
+        update(&mut world, 1.0);
+        assert_eq!(get_spawned(&world), [1, 1, 0]);
+        assert!(!world.resource::<SpawnerInfo>().is_finished());
+
+        update(&mut world, 1.0);
+        assert_eq!(get_spawned(&world), [1, 1, 0]);
+
+        // The next wave counts its interval from when it starts
+        destroy_spawned(&mut world, 1);
+        update(&mut world, 0.5);
+        assert_eq!(world.resource::<SpawnerInfo>().current_wave, 1);
+        update(&mut world, 0.5);
+        assert_eq!(get_spawned(&world), [1, 1, 0]);
+        update(&mut world, 0.5);
+        assert_eq!(get_spawned(&world), [1, 1, 1]);
+        assert!(!world.resource::<SpawnerInfo>().is_finished());
+
+        // Tanks left alive from spawners outside waves do not hold it back
+        destroy_spawned(&mut world, 2);
+        update(&mut world, 0.0);
+        assert!(world.resource::<SpawnerInfo>().is_finished());
+    }
+
+    #[test]
+    fn spawners_that_never_finish_are_disabled() {
+        let mut never_finishing = new_spawner_config(400.0, 2, 0);
+        let mut every_tick = new_spawner_config(700.0, 2, 2);
+        every_tick.interval = 0.0;
+        let mut world = new_world(&GameMapConfig {
+            spawners: vec![never_finishing.clone(), every_tick.clone()],
+            ..Default::default()
+        });
+
+        assert!(world.resource::<SpawnerInfo>().is_finished());
+        update(&mut world, 1.0);
+        assert_eq!(get_spawned(&world), [0, 0]);
+
+        never_finishing.max_alive = 1;
+        every_tick.interval = 1.0;
+        assert!(never_finishing.get_problem().is_none());
+        assert!(every_tick.get_problem().is_none());
+    }
+}
@@ -2,286 +2,480 @@ use crate::ai::*;
 use crate::game_lib::*;
 use crate::game_map::*;
 use crate::game_obj::*;
+use crate::game_state::*;
+use crate::input::*;
+use crate::my_error::*;
+use crate::pathfinding::*;
+use crate::replay::*;
+use crate::simulation::*;
 use crate::utils::*;
+use bevy::ecs::entity::{EntityHashMap, EntityHashSet};
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
-use std::collections::{HashMap, HashSet};
 use std::path::Path;
 
-pub fn setup_game(
-    args: Res<Args>,
-    asset_server: Res<AssetServer>,
-    mut texture_atlas_layouts: ResMut<Assets<TextureAtlasLayout>>,
-    mut commands: Commands,
-    mut exit_app: MessageWriter<AppExit>,
-    mut window: Single<&mut Window>,
-) {
-    let Some(game_lib) = load_game_lib(
-        args.config_path.as_path(),
-        asset_server.as_ref(),
-        texture_atlas_layouts.as_mut(),
-        &mut exit_app,
-    ) else {
-        return;
+pub fn setup_game(args: Res<Args>, mut commands: Commands, mut exit_app: MessageWriter<AppExit>) {
+    let header = match setup_replay(args.as_ref(), &mut commands) {
+        Ok(h) => h,
+        Err(err) => {
+            error!("Failed to set up game: {}", err);
+            exit_app.write(AppExit::error());
+            return;
+        }
     };
 
-    init_window(window.as_mut(), &game_lib);
-    commands.spawn(Camera2d);
+    let Some(game_lib) = load_game_lib(header.config_path.as_path(), &mut exit_app) else {
+        return;
+    };
 
-    let mut game_obj_lib = GameObjInfoLib(HashMap::new());
-    let despawn_pool = DespawnPool(HashSet::new());
+    // A saved game brings its own RNG state, which replaces this one
+    commands.insert_resource(GameRng::new(header.seed));
+    let mut game_obj_lib = GameObjInfoLib(EntityHashMap::new());
 
     let map = match GameMap::load(
-        args.map_path.as_path(),
+        header.map_path.as_path(),
         &game_lib,
+        header.mode,
         &mut game_obj_lib,
         &mut commands,
     ) {
         Ok(m) => m,
         Err(err) => {
-            error!("Failed to load map from {:?}: {}", args.map_path, err);
+            error!("Failed to load map from {:?}: {}", header.map_path, err);
             exit_app.write(AppExit::error());
             return;
         }
     };
 
+    insert_game_world(map, game_obj_lib, &game_lib, &mut commands);
     commands.insert_resource(game_lib);
-    commands.insert_resource(game_obj_lib);
-    commands.insert_resource(map);
-    commands.insert_resource(despawn_pool);
+    commands.insert_resource(header.mode);
+    commands.insert_resource(header);
 
     info!("Setup finished");
 }
 
-pub fn process_input(
-    mut commands: Commands,
-    keys: Res<ButtonInput<KeyCode>>,
-    game_lib: Res<GameLib>,
-    mut player: Single<(Entity, &mut Transform, &mut ShootComponent), With<PlayerComponent>>,
-    mut map: ResMut<GameMap>,
-    mut game_obj_lib: ResMut<GameObjInfoLib>,
-    mut despawn_pool: ResMut<DespawnPool>,
-    time: Res<Time>,
-) {
-    if keys.just_pressed(KeyCode::ArrowRight) || keys.pressed(KeyCode::ArrowRight) {
-        steer_player(
-            Direction::Right,
-            game_lib.as_ref(),
-            &mut player,
-            map.as_mut(),
-            game_obj_lib.as_mut(),
-            despawn_pool.as_mut(),
-            &mut commands,
-            time.as_ref(),
-        );
-    } else if keys.just_pressed(KeyCode::ArrowLeft) || keys.pressed(KeyCode::ArrowLeft) {
-        steer_player(
-            Direction::Left,
-            game_lib.as_ref(),
-            &mut player,
-            map.as_mut(),
-            game_obj_lib.as_mut(),
-            despawn_pool.as_mut(),
-            &mut commands,
-            time.as_ref(),
-        );
-    } else if keys.just_pressed(KeyCode::ArrowUp) || keys.pressed(KeyCode::ArrowUp) {
-        steer_player(
-            Direction::Up,
-            game_lib.as_ref(),
-            &mut player,
-            map.as_mut(),
-            game_obj_lib.as_mut(),
-            despawn_pool.as_mut(),
-            &mut commands,
-            time.as_ref(),
-        );
-    } else if keys.just_pressed(KeyCode::ArrowDown) || keys.pressed(KeyCode::ArrowDown) {
-        steer_player(
-            Direction::Down,
-            game_lib.as_ref(),
-            &mut player,
-            map.as_mut(),
-            game_obj_lib.as_mut(),
-            despawn_pool.as_mut(),
-            &mut commands,
-            time.as_ref(),
+// Everything that belongs to the loaded map and goes away when it is reloaded
+pub type GameWorldFilter = Or<(With<GameObjComponent>, With<ExplosionComponent>)>;
+
+// What restarting or loading a game needs to replace the game world
+#[derive(SystemParam)]
+pub struct GameWorldReloader<'w, 's> {
+    pub game_lib: Res<'w, GameLib>,
+    pub world_query: Query<'w, 's, Entity, GameWorldFilter>,
+    pub commands: Commands<'w, 's>,
+}
+
+impl GameWorldReloader<'_, '_> {
+    pub fn reload<P: AsRef<Path>>(&mut self, map_path: P, mode: GameMode) -> Result<(), MyError> {
+        let game_lib = self.game_lib.as_ref();
+        let map_config = GameMapConfig::read(map_path, game_lib.get_game_config())?;
+
+        for entity in self.world_query.iter() {
+            self.commands.entity(entity).despawn();
+        }
+
+        let mut game_obj_lib = GameObjInfoLib(EntityHashMap::new());
+        let map = GameMap::from_config(
+            &map_config,
+            game_lib,
+            mode,
+            &mut game_obj_lib,
+            &mut self.commands,
         );
-    } else if keys.just_pressed(KeyCode::KeyF) || keys.pressed(KeyCode::KeyF) {
-        shoot_player_missile(
-            &mut player,
-            &mut commands,
-            game_lib.as_ref(),
-            map.as_mut(),
-            game_obj_lib.as_mut(),
-            time.as_ref(),
+        insert_game_world(map, game_obj_lib, game_lib, &mut self.commands);
+
+        Ok(())
+    }
+}
+
+// The game world that moving tanks, shooting and explosions update
+#[derive(SystemParam)]
+pub struct GameWorldParams<'w, 's> {
+    commands: Commands<'w, 's>,
+    game_lib: Res<'w, GameLib>,
+    map: ResMut<'w, GameMap>,
+    game_obj_lib: ResMut<'w, GameObjInfoLib>,
+    despawn_pool: ResMut<'w, DespawnPool>,
+    time: Res<'w, Time>,
+}
+
+pub fn insert_game_world(
+    map: GameMap,
+    game_obj_lib: GameObjInfoLib,
+    game_lib: &GameLib,
+    commands: &mut Commands,
+) {
+    commands.insert_resource(map.build_nav_grid(&game_obj_lib, game_lib));
+    commands.insert_resource(game_obj_lib);
+    commands.insert_resource(map);
+    commands.insert_resource(DespawnPool(EntityHashSet::new()));
+}
+
+pub fn apply_player_input(
+    player_inputs: Res<PlayerInputs>,
+    mut player_query: Query<(Entity, &PlayerComponent, &mut ShootComponent)>,
+    mut game_world: GameWorldParams,
+) {
+    let mut players: Vec<_> = player_query.iter_mut().collect();
+    players.sort_by_key(|(_, player_comp, _)| player_comp.player_id);
+
+    for (entity, player_comp, mut shoot_comp) in players {
+        if !game_world.game_obj_lib.contains_key(&entity) {
+            continue;
+        }
+        let input = player_inputs
+            .get(player_comp.player_id)
+            .copied()
+            .unwrap_or_default();
+
+        move_tank(
+            &entity,
+            input.move_direction.map(Vec2::from),
+            Some(shoot_comp.as_mut()),
+            &mut game_world,
         );
+
+        if input.shoot {
+            shoot_player_missile(&entity, shoot_comp.as_mut(), &mut game_world);
+        } else {
+            prime_shooter(shoot_comp.as_mut(), game_world.time.as_ref());
+        }
     }
 }
 
 pub fn update_missiles(
-    mut missile_query: Query<(Entity, &mut Transform), With<MissileComponent>>,
-    game_lib: Res<GameLib>,
-    mut map: ResMut<GameMap>,
-    mut game_obj_lib: ResMut<GameObjInfoLib>,
-    mut despawn_pool: ResMut<DespawnPool>,
-    mut commands: Commands,
-    time: Res<Time>,
+    missile_query: Query<Entity, With<MissileComponent>>,
+    mut game_world: GameWorldParams,
 ) {
-    let mut dead_objs: HashMap<Entity, DeadGameObjInfo> = HashMap::new();
+    let mut dead_objs: EntityHashMap<DeadGameObjInfo> = EntityHashMap::new();
 
-    for (entity, mut transform) in missile_query.iter_mut() {
-        if despawn_pool.contains(&entity) {
+    for entity in missile_query.iter() {
+        if game_world.despawn_pool.contains(&entity) {
             continue;
         }
 
-        let Some(obj) = game_obj_lib.get(&entity).cloned() else {
+        let Some(obj) = game_world.game_obj_lib.get(&entity).cloned() else {
             error!("Failed to find entity in GameObjInfoLib");
             continue;
         };
 
-        let (collide, new_pos) = map.get_missile_new_pos(
+        let (collide, new_pos) = game_world.map.get_missile_new_pos(
             &entity,
             &obj,
-            game_obj_lib.as_ref(),
-            despawn_pool.as_ref(),
-            time.as_ref(),
+            game_world.game_lib.as_ref(),
+            game_world.game_obj_lib.as_ref(),
+            game_world.despawn_pool.as_ref(),
+            game_world.time.as_ref(),
         );
 
-        let screen_pos = game_lib.get_screen_pos(&new_pos);
-        transform.translation.x = screen_pos.x;
-        transform.translation.y = screen_pos.y;
-
         update_obj_pos_direction(
             &entity,
             &new_pos,
             &obj.direction,
-            game_obj_lib.as_mut(),
-            map.as_mut(),
+            game_world.game_obj_lib.as_mut(),
+            game_world.map.as_mut(),
         );
 
         if collide {
-            if let Some(explosion_name) = game_lib
-                .get_obj_config(obj.config_index)
-                .explosion_name
-                .as_ref()
-            {
-                explode(
-                    &new_pos,
-                    obj.side,
-                    explosion_name,
-                    &mut dead_objs,
-                    map.as_ref(),
-                    game_lib.as_ref(),
-                    game_obj_lib.as_mut(),
-                    despawn_pool.as_ref(),
-                    &mut commands,
-                );
-            }
+            explode(
+                &GameObjInfo {
+                    pos: new_pos,
+                    ..obj
+                },
+                &mut dead_objs,
+                &mut game_world,
+            );
 
+            // The missile may have just crossed into a new cell
             dead_objs.insert(
-                entity.clone(),
+                entity,
                 DeadGameObjInfo {
-                    map_pos: obj.map_pos,
+                    map_pos: game_world.map.get_map_pos(&new_pos),
                     is_phasing: false,
                 },
             );
         }
     }
 
-    process_dead_objs(
-        &dead_objs,
-        map.as_mut(),
-        game_obj_lib.as_mut(),
-        despawn_pool.as_mut(),
-        &mut commands,
-        game_lib.as_ref(),
-    );
+    process_dead_objs(&dead_objs, &mut game_world);
 }
 
 pub fn update_explosions(
-    mut explosion_query: Query<(Entity, &mut Sprite, &mut ExplosionComponent)>,
+    mut explosion_query: Query<(Entity, &mut ExplosionComponent)>,
     mut despawn_pool: ResMut<DespawnPool>,
     time: Res<Time>,
 ) {
-    for (entity, mut sprite, mut explosion_comp) in explosion_query.iter_mut() {
+    for (entity, mut explosion_comp) in explosion_query.iter_mut() {
         explosion_comp.timer.tick(time.delta());
         if explosion_comp.timer.is_finished() {
-            if let Some(atlas) = sprite.texture_atlas.as_mut() {
-                if atlas.index < explosion_comp.last_index - 1 {
-                    atlas.index += 1;
-                } else {
-                    despawn_pool.insert(entity);
-                }
+            if explosion_comp.index < explosion_comp.last_index - 1 {
+                explosion_comp.index += 1;
+            } else {
+                despawn_pool.insert(entity);
             }
         }
     }
 }
 
 pub fn update_phasing_objs(
-    mut phasing_obj_query: Query<(Entity, &mut Sprite, &mut PhasingTimer)>,
+    mut phasing_obj_query: Query<(Entity, &mut PhasingComponent)>,
     mut despawn_pool: ResMut<DespawnPool>,
-    game_lib: Res<GameLib>,
     time: Res<Time>,
 ) {
-    for (entity, mut sprite, mut phasing_timer) in phasing_obj_query.iter_mut() {
-        phasing_timer.tick(time.delta());
-        if !phasing_timer.is_finished() {
-            let alpha =
-                (1.0 - phasing_timer.elapsed_secs() / game_lib.get_game_config().phasing_duration).max(0.0);
-            sprite.color.set_alpha(alpha);
-        } else {
+    for (entity, mut phasing_comp) in phasing_obj_query.iter_mut() {
+        phasing_comp.timer.tick(time.delta());
+        if phasing_comp.timer.is_finished() {
             despawn_pool.insert(entity);
         }
     }
 }
 
-// pub fn update_ai(
-//     mut ai_tank_query: Query<(Entity, &mut AIComponent)>,
-//     game_lib: Res<GameLib>,
-//     mut game_obj_lib: ResMut<GameObjInfoLib>,
-//     player_info: Res<PlayerInfo>,
-//     time: Res<Time>,
-// ) {
-//     let Some((player_pos, player_collide_span)) = get_player_info_for_ai(
-//         player_info.as_ref(),
-//         game_obj_lib.as_ref(),
-//         game_lib.as_ref(),
-//     ) else {
-//         return;
-//     };
-
-//     for (entity, mut ai_comp) in ai_tank_query.iter_mut() {
-//         let Some((obj, ai_config)) =
-//             get_obj_for_ai(&entity, game_obj_lib.as_mut(), game_lib.as_ref())
-//         else {
-//             continue;
-//         };
-
-//         update_ai_for_obj(
-//             obj,
-//             ai_comp.as_mut(),
-//             ai_config,
-//             &player_pos,
-//             player_collide_span,
-//             time.as_ref(),
-//         );
-//     }
-// }
+pub fn update_ai(
+    mut ai_tank_query: Query<(Entity, &mut AIComponent, Option<&mut ShootComponent>)>,
+    nav_grid: Res<NavGrid>,
+    mut rng: ResMut<GameRng>,
+    mut game_world: GameWorldParams,
+) {
+    let targets = get_targets_for_ai(
+        game_world.map.as_ref(),
+        game_world.game_obj_lib.as_ref(),
+        game_world.game_lib.as_ref(),
+    );
+
+    for (entity, mut ai_comp, mut shoot_comp) in ai_tank_query.iter_mut() {
+        if game_world.despawn_pool.contains(&entity) {
+            continue;
+        }
+
+        let Some((obj, ai_config)) = get_obj_for_ai(
+            &entity,
+            game_world.game_obj_lib.as_mut(),
+            game_world.game_lib.as_ref(),
+        ) else {
+            continue;
+        };
+        let Some(target) = get_nearest_enemy(&entity, obj, &targets, game_world.game_lib.as_ref())
+        else {
+            continue;
+        };
+
+        update_ai_for_obj(
+            obj,
+            ai_comp.as_mut(),
+            ai_config,
+            &target,
+            nav_grid.as_ref(),
+            &mut rng.rng,
+            game_world.time.as_ref(),
+        );
+
+        let move_direction = matches!(ai_comp.action, Some(Action::Move)).then_some(obj.direction);
+        if move_tank(
+            &entity,
+            move_direction,
+            shoot_comp.as_deref_mut(),
+            &mut game_world,
+        ) {
+            ai_comp.collision_happened = true;
+        }
+
+        // Moving may have slid the tank or run it into a missile
+        let Some(obj) = game_world.game_obj_lib.get(&entity).copied() else {
+            continue;
+        };
+        if game_world.despawn_pool.contains(&entity) {
+            continue;
+        }
+
+        // Tanks without a shoot config can only chase
+        if let Some(Action::Shoot) = ai_comp.action
+            && let Some(mut shoot_comp) = shoot_comp
+        {
+            shoot_comp.shoot_pos = obj.pos + obj.direction.rotate(shoot_comp.init_shoot_pos);
+            if has_clear_shot(&entity, &obj, shoot_comp.as_ref(), &game_world) {
+                shoot_missile(
+                    shoot_comp.as_mut(),
+                    &obj.direction,
+                    obj.team,
+                    &mut game_world,
+                );
+            } else {
+                prime_shooter(shoot_comp.as_mut(), game_world.time.as_ref());
+            }
+        }
+    }
+}
+
+pub fn detect_player_deaths(
+    dead_player_query: Query<
+        (Entity, &PlayerComponent, &PhasingComponent),
+        Added<PhasingComponent>,
+    >,
+    mut player_died: MessageWriter<PlayerDied>,
+) {
+    for (entity, player_comp, phasing_comp) in dead_player_query.iter() {
+        player_died.write(PlayerDied {
+            player_id: player_comp.player_id,
+            entity,
+            pos: phasing_comp.pos,
+        });
+    }
+}
+
+// Destroyed tiles and bases open up new paths for the AI
+pub fn update_nav_grid(
+    dead_obj_query: Query<&GameObjComponent, Added<PhasingComponent>>,
+    game_lib: Res<GameLib>,
+    map: Res<GameMap>,
+    game_obj_lib: Res<GameObjInfoLib>,
+    mut nav_grid: ResMut<NavGrid>,
+) {
+    let obstacle_removed = dead_obj_query.iter().any(|obj_comp| {
+        let obj_config = game_lib.get_obj_config(obj_comp.config_index);
+        obj_config.obj_type.is_obstacle()
+            && obj_config.terrain.blocks_tanks()
+            && obj_config.collide_span > 0.0
+    });
+
+    if obstacle_removed {
+        *nav_grid = map.build_nav_grid(game_obj_lib.as_ref(), game_lib.as_ref());
+    }
+}
+
+pub fn detect_base_deaths(
+    dead_base_query: Query<&PhasingComponent, (With<BaseComponent>, Added<PhasingComponent>)>,
+    mut base_destroyed: MessageWriter<BaseDestroyed>,
+) {
+    for phasing_comp in dead_base_query.iter() {
+        base_destroyed.write(BaseDestroyed {
+            team: phasing_comp.team,
+            pos: phasing_comp.pos,
+        });
+    }
+}
+
+pub fn handle_player_deaths(
+    mut player_died: MessageReader<PlayerDied>,
+    game_lib: Res<GameLib>,
+    mut player_info: ResMut<PlayerInfo>,
+) {
+    for msg in player_died.read() {
+        // Players loaded while phasing out lost the life before the save
+        let Some(slot) = player_info
+            .get_mut(&msg.player_id)
+            .filter(|slot| slot.entity == msg.entity)
+        else {
+            continue;
+        };
+        info!("Player {} destroyed at {:?}", msg.player_id, msg.pos);
+        slot.lives = slot.lives.saturating_sub(1);
+        if slot.lives > 0 {
+            slot.respawn_timer = Some(Timer::from_seconds(
+                game_lib.get_game_config().respawn_delay,
+                TimerMode::Once,
+            ));
+        } else {
+            info!("Player {} is out of lives", msg.player_id);
+        }
+    }
+}
+
+pub fn respawn_players(
+    mut player_info: ResMut<PlayerInfo>,
+    nav_grid: Res<NavGrid>,
+    mut game_world: GameWorldParams,
+) {
+    for (player_id, slot) in player_info.iter_mut() {
+        let Some(respawn_timer) = slot.respawn_timer.as_mut() else {
+            continue;
+        };
+        respawn_timer.tick(game_world.time.delta());
+        if !respawn_timer.is_finished() {
+            continue;
+        }
+
+        // Nowhere on the map is free, so the life is lost rather than leaving
+        // the player waiting forever
+        let Some((pos, direction)) =
+            find_spawn_pos(*player_id, slot, nav_grid.as_ref(), &game_world)
+        else {
+            slot.respawn_timer = None;
+            slot.lives = slot.lives.saturating_sub(1);
+            warn!(
+                "Player {} found no free place to respawn, {} lives left",
+                player_id, slot.lives
+            );
+            continue;
+        };
+
+        let game_lib = game_world.game_lib.as_ref();
+        let game_obj_lib = game_world.game_obj_lib.as_mut();
+        let Some(entity) = game_world.map.add_obj(
+            slot.config_index,
+            &pos,
+            &direction,
+            game_lib,
+            game_obj_lib,
+            &mut game_world.commands,
+        ) else {
+            continue;
+        };
+
+        if let Some(obj) = game_obj_lib.get_mut(&entity) {
+            obj.team = slot.team;
+            obj.invulnerable = true;
+        }
+        game_world.commands.entity(entity).insert((
+            PlayerComponent {
+                player_id: *player_id,
+            },
+            InvulnerableComponent(Timer::from_seconds(
+                game_lib.get_game_config().invulnerable_duration,
+                TimerMode::Once,
+            )),
+        ));
+
+        slot.entity = entity;
+        slot.respawn_timer = None;
+        info!(
+            "Player {} respawned at {:?}, {} lives left",
+            player_id, pos, slot.lives
+        );
+    }
+}
+
+pub fn update_invulnerability(
+    mut commands: Commands,
+    mut invulnerable_query: Query<(Entity, &mut InvulnerableComponent)>,
+    mut game_obj_lib: ResMut<GameObjInfoLib>,
+    time: Res<Time>,
+) {
+    for (entity, mut invulnerable_comp) in invulnerable_query.iter_mut() {
+        invulnerable_comp.tick(time.delta());
+        if !invulnerable_comp.is_finished() {
+            continue;
+        }
+
+        if let Some(obj) = game_obj_lib.get_mut(&entity) {
+            obj.invulnerable = false;
+        }
+        commands.entity(entity).remove::<InvulnerableComponent>();
+    }
+}
 
 pub fn cleanup(mut commands: Commands, mut despawn_pool: ResMut<DespawnPool>) {
     for e in despawn_pool.iter() {
-        commands.entity(e.clone()).despawn();
+        commands.entity(*e).despawn();
     }
     despawn_pool.clear();
 }
 
-fn load_game_lib<P: AsRef<Path>>(
+pub fn load_game_lib<P: AsRef<Path>>(
     config_path: P,
-    asset_server: &AssetServer,
-    texture_atlas_layouts: &mut Assets<TextureAtlasLayout>,
     exit_app: &mut MessageWriter<AppExit>,
 ) -> Option<GameLib> {
-    let game_lib = match GameLib::new(config_path, asset_server, texture_atlas_layouts) {
+    let game_lib = match GameLib::new(config_path) {
         Ok(lib) => lib,
         Err(err) => {
             error!("Failed to initialize GameLib: {}", err);
@@ -293,83 +487,103 @@ fn load_game_lib<P: AsRef<Path>>(
     Some(game_lib)
 }
 
-fn init_window(window: &mut Window, game_lib: &GameLib) {
-    let config = game_lib.get_game_config();
-    window.resolution.set(config.window_width(), config.window_height());
-}
-
-fn steer_player(
-    d: Direction,
-    game_lib: &GameLib,
-    player: &mut Single<(Entity, &mut Transform, &mut ShootComponent), With<PlayerComponent>>,
-    map: &mut GameMap,
-    game_obj_lib: &mut GameObjInfoLib,
-    despawn_pool: &mut DespawnPool,
-    commands: &mut Commands,
-    time: &Time,
-) {
-    if despawn_pool.contains(&player.0) {
-        return;
+// Turns the tank if it faces another way, otherwise moves it. Without a move
+// a tank on ice keeps sliding the way it faces. Returns whether it collided.
+fn move_tank(
+    entity: &Entity,
+    move_direction: Option<Vec2>,
+    shoot_comp: Option<&mut ShootComponent>,
+    game_world: &mut GameWorldParams,
+) -> bool {
+    let map = game_world.map.as_mut();
+    let game_obj_lib = game_world.game_obj_lib.as_mut();
+    let despawn_pool = game_world.despawn_pool.as_ref();
+    if despawn_pool.contains(entity) {
+        return false;
     }
 
-    let new_direction: Vec2 = d.into();
-    let Some(obj) = game_obj_lib.get(&player.0).cloned() else {
-        warn!("Cannot find player in map");
-        return;
+    let Some(obj) = game_obj_lib.get(entity).cloned() else {
+        warn!("Cannot find tank in map");
+        return false;
+    };
+    let Some(new_direction) = move_direction.or_else(|| {
+        map.is_on_terrain(&obj.pos, Terrain::Ice, game_obj_lib)
+            .then_some(obj.direction)
+    }) else {
+        return false;
     };
-    let mut new_pos = obj.pos;
-    let obj_config = game_lib.get_obj_config(obj.config_index);
 
-    if new_direction != obj.direction {
-        player.1.rotation = get_rotation(&new_direction);
-    } else {
-        let (_, pos) = map.get_tank_new_pos(&player.0, &obj, game_obj_lib, despawn_pool, time);
-        new_pos = pos;
+    let mut collide = false;
+    let mut new_pos = obj.pos;
+    if new_direction == obj.direction {
+        (collide, new_pos) = map.get_tank_new_pos(
+            entity,
+            &obj,
+            game_obj_lib,
+            despawn_pool,
+            game_world.time.as_ref(),
+        );
+    }
 
-        let new_screen_pos = game_lib.get_screen_pos(&new_pos);
-        player.1.translation.x = new_screen_pos.x;
-        player.1.translation.y = new_screen_pos.y;
+    update_obj_pos_direction(entity, &new_pos, &new_direction, game_obj_lib, map);
+    if let Some(shoot_comp) = shoot_comp {
+        shoot_comp.shoot_pos = new_pos + new_direction.rotate(shoot_comp.init_shoot_pos);
     }
 
-    update_obj_pos_direction(&player.0, &new_pos, &new_direction, game_obj_lib, map);
-    player.2.shoot_pos = obj.pos + new_direction.rotate(player.2.init_shoot_pos);
-
-    capture_collide_missiles(
-        &new_pos,
-        obj_config,
-        map,
-        game_lib,
-        game_obj_lib,
-        despawn_pool,
-        commands,
-    );
+    capture_collide_missiles(&new_pos, &obj, game_world);
 
+    collide
 }
 
 fn shoot_player_missile(
-    player: &mut Single<(Entity, &mut Transform, &mut ShootComponent), With<PlayerComponent>>,
-    commands: &mut Commands,
-    game_lib: &GameLib,
-    map: &mut GameMap,
-    game_obj_lib: &mut GameObjInfoLib,
-    time: &Time,
+    entity: &Entity,
+    shoot_comp: &mut ShootComponent,
+    game_world: &mut GameWorldParams,
 ) {
-    player.2.timer.tick(time.delta());
-    if player.2.timer.just_finished() {
-        let Some(direction) = game_obj_lib.get(&player.0).map(|obj| obj.direction) else {
-            error!("Failed to find player in GameObjInfoLib");
-            return;
-        };
-        map.add_obj(
-            player.2.missile_config_index,
-            &player.2.shoot_pos,
-            &direction,
-            game_lib,
+    let Some((direction, team)) = game_world
+        .game_obj_lib
+        .get(entity)
+        .map(|obj| (obj.direction, obj.team))
+    else {
+        error!("Failed to find player in GameObjInfoLib");
+        return;
+    };
+
+    shoot_missile(shoot_comp, &direction, team, game_world);
+}
+
+// Missiles take the shooter's team, so a player's shots in versus mode hurt
+// the other player
+fn shoot_missile(
+    shoot_comp: &mut ShootComponent,
+    direction: &Vec2,
+    team: usize,
+    game_world: &mut GameWorldParams,
+) {
+    let game_obj_lib = game_world.game_obj_lib.as_mut();
+
+    shoot_comp.timer.tick(game_world.time.delta());
+    if shoot_comp.timer.just_finished() {
+        if let Some(missile) = game_world.map.add_obj(
+            shoot_comp.missile_config_index,
+            &shoot_comp.shoot_pos,
+            direction,
+            game_world.game_lib.as_ref(),
             game_obj_lib,
-            commands,
-        );
+            &mut game_world.commands,
+        ) && let Some(obj) = game_obj_lib.get_mut(&missile)
+        {
+            obj.team = team;
+        }
+
+        shoot_comp.timer.reset();
+    }
+}
 
-        player.2.timer.reset();
+// Tick the shoot timer without letting it wrap, so the next shot fires at once
+fn prime_shooter(shoot_comp: &mut ShootComponent, time: &Time) {
+    if shoot_comp.timer.remaining() > time.delta() {
+        shoot_comp.timer.tick(time.delta());
     }
 }
 
@@ -385,123 +599,109 @@ fn update_obj_pos_direction(
         return;
     };
 
-    obj.pos = new_pos.clone();
+    obj.pos = *new_pos;
     let new_map_pos = map.get_map_pos(new_pos);
     map.relocate(entity, &obj.map_pos, &new_map_pos);
-    obj.direction = new_direction.clone();
+    obj.direction = *new_direction;
     obj.map_pos = new_map_pos;
 }
 
-fn capture_collide_missiles(
-    pos: &Vec2,
-    obj_config: &GameObjConfig,
-    map: &mut GameMap,
-    game_lib: &GameLib,
-    game_obj_lib: &mut GameObjInfoLib,
-    despawn_pool: &mut DespawnPool,
-    commands: &mut Commands,
-) {
-    let (start_map_pos, end_map_pos) = map.get_collide_region_pass(pos, obj_config.collide_span);
-    let mut dead_objs: HashMap<Entity, DeadGameObjInfo> = HashMap::new();
+fn capture_collide_missiles(pos: &Vec2, tank: &GameObjInfo, game_world: &mut GameWorldParams) {
+    let game_lib = game_world.game_lib.as_ref();
+    let collide_span = game_lib.get_obj_config(tank.config_index).collide_span;
+    let (start_map_pos, end_map_pos) = game_world.map.get_collide_region_pass(pos, collide_span);
+    let mut missiles = Vec::new();
 
     for row in start_map_pos.row..=end_map_pos.row {
         for col in start_map_pos.col..=end_map_pos.col {
-            for e in map.map[row][col].iter() {
-                if despawn_pool.contains(e) {
+            for e in game_world.map.map[row][col].iter() {
+                if game_world.despawn_pool.contains(e) {
                     continue;
                 }
-                let Some(obj2) = game_obj_lib.get(e).cloned() else {
+                let Some(obj2) = game_world.game_obj_lib.get(e).cloned() else {
                     warn!("Cannot find entity {e} in map");
                     continue;
                 };
-                let obj_config2 = game_lib.get_obj_config(obj2.config_index);
 
                 if obj2.obj_type == GameObjType::Missile
-                    && obj2.side != obj_config.side
-                {
-                    if check_collide_obj_pass(
+                    && game_lib.get_relation(tank.team, obj2.team) != Relation::Ally
+                    && check_collide_obj_pass(
                         pos,
-                        obj_config.collide_span,
+                        collide_span,
                         &obj2.pos,
-                        obj_config2.collide_span,
-                    ) {
-                        if let Some(explosion_name) = obj_config2.explosion_name.as_ref() {
-                            explode(
-                                &obj2.pos,
-                                obj2.side,
-                                explosion_name,
-                                &mut dead_objs,
-                                map,
-                                game_lib,
-                                game_obj_lib,
-                                despawn_pool,
-                                commands,
-                            );
-                        }
-
-                        dead_objs.insert(
-                            e.clone(),
-                            DeadGameObjInfo {
-                                map_pos: obj2.map_pos,
-                                is_phasing: false,
-                            },
-                        );
-                    }
+                        game_lib.get_obj_config(obj2.config_index).collide_span,
+                    )
+                {
+                    missiles.push((*e, obj2));
                 }
             }
         }
     }
 
-    process_dead_objs(
-        &dead_objs,
-        map,
-        game_obj_lib,
-        despawn_pool,
-        commands,
-        game_lib,
-    );
+    let mut dead_objs: EntityHashMap<DeadGameObjInfo> = EntityHashMap::new();
+    for (e, missile) in missiles {
+        explode(&missile, &mut dead_objs, game_world);
+        dead_objs.insert(
+            e,
+            DeadGameObjInfo {
+                map_pos: missile.map_pos,
+                is_phasing: false,
+            },
+        );
+    }
+
+    process_dead_objs(&dead_objs, game_world);
 }
 
+// Missiles without an explosion just disappear
 fn explode(
-    pos: &Vec2,
-    side: GameObjSide,
-    explosion_name: &String,
-    dead_objs: &mut HashMap<Entity, DeadGameObjInfo>,
-    map: &GameMap,
-    game_lib: &GameLib,
-    game_obj_lib: &mut GameObjInfoLib,
-    despawn_pool: &DespawnPool,
-    commands: &mut Commands,
+    missile: &GameObjInfo,
+    dead_objs: &mut EntityHashMap<DeadGameObjInfo>,
+    game_world: &mut GameWorldParams,
 ) {
+    let game_lib = game_world.game_lib.as_ref();
+    let Some(explosion_name) = game_lib
+        .get_obj_config(missile.config_index)
+        .explosion_name
+        .as_ref()
+    else {
+        return;
+    };
     let Some(explosion_config) = game_lib.get_explosion_config(explosion_name) else {
         error!("Failed to find ExplosionConfig {}", explosion_name);
         return;
     };
 
+    let (damage, explode_span) = (explosion_config.damage, explosion_config.explode_span);
+    create_explosion(
+        &missile.pos,
+        explosion_name,
+        explosion_config,
+        &mut game_world.commands,
+    );
+
     do_damage(
-        pos,
-        side,
-        explosion_config.damage,
-        explosion_config.explode_span,
+        &missile.pos,
+        missile.team,
+        damage,
+        explode_span,
         dead_objs,
-        map,
-        game_obj_lib,
-        despawn_pool,
+        game_world,
     );
-
-    create_explosion(pos, explosion_name, explosion_config, game_lib, commands);
 }
 
 fn do_damage(
     pos: &Vec2,
-    side: GameObjSide,
+    team: usize,
     damage: f32,
     explode_span: f32,
-    dead_objs: &mut HashMap<Entity, DeadGameObjInfo>,
-    map: &GameMap,
-    game_obj_lib: &mut GameObjInfoLib,
-    despawn_pool: &DespawnPool,
+    dead_objs: &mut EntityHashMap<DeadGameObjInfo>,
+    game_world: &mut GameWorldParams,
 ) {
+    let map = game_world.map.as_ref();
+    let game_lib = game_world.game_lib.as_ref();
+    let game_obj_lib = game_world.game_obj_lib.as_mut();
+    let despawn_pool = game_world.despawn_pool.as_ref();
     let (start_pos, end_pos) = map.get_collide_region_pass(pos, explode_span);
 
     for row in start_pos.row..=end_pos.row {
@@ -515,21 +715,20 @@ fn do_damage(
                     continue;
                 };
 
-                if obj.obj_type == GameObjType::Tank
-                    && obj.side != side
+                if !obj.invulnerable
+                    && game_lib.get_relation(team, obj.team) != Relation::Ally
                     && check_collide_obj_pass(pos, explode_span, &obj.pos, obj.collide_span)
+                    && let Some(hp) = obj.hp.as_mut()
                 {
-                    if let Some(hp) = obj.hp.as_mut() {
-                        *hp = (*hp - damage).max(0.0);
-                        if *hp == 0.0 {
-                            dead_objs.insert(
-                                e.clone(),
-                                DeadGameObjInfo {
-                                    map_pos: obj.map_pos,
-                                    is_phasing: true,
-                                },
-                            );
-                        }
+                    *hp = (*hp - damage).max(0.0);
+                    if *hp == 0.0 {
+                        dead_objs.insert(
+                            *e,
+                            DeadGameObjInfo {
+                                map_pos: obj.map_pos,
+                                is_phasing: true,
+                            },
+                        );
                     }
                 }
             }
@@ -539,89 +738,293 @@ fn do_damage(
 
 fn create_explosion(
     pos: &Vec2,
-    explosion_name: &String,
+    explosion_name: &str,
     explosion_config: &ExplosionConfig,
-    game_lib: &GameLib,
     commands: &mut Commands,
 ) {
-    let Some(texture) = game_lib.get_image(&explosion_config.image) else {
-        return;
-    };
-    let Some(layout) = game_lib.get_texture_atlas_layout(explosion_name) else {
-        return;
-    };
-
-    let screen_pos = game_lib.get_screen_pos(pos);
-    let frame_duration = 1.0 / explosion_config.frames_per_second as f32;
-
-    commands.spawn((
-        Sprite::from_atlas_image(texture, TextureAtlas { layout, index: 0 }),
-        Transform::from_xyz(screen_pos.x, screen_pos.y, explosion_config.z),
-        ExplosionComponent {
-            timer: Timer::from_seconds(frame_duration, TimerMode::Repeating),
-            last_index: explosion_config.frame_count as usize,
-        },
+    commands.spawn(ExplosionComponent::new(
+        pos,
+        explosion_name,
+        explosion_config,
     ));
 }
 
-fn process_dead_objs(
-    dead_objs: &HashMap<Entity, DeadGameObjInfo>,
-    map: &mut GameMap,
-    game_obj_lib: &mut GameObjInfoLib,
-    despawn_pool: &mut DespawnPool,
-    commands: &mut Commands,
-    game_lib: &GameLib,
-) {
+fn process_dead_objs(dead_objs: &EntityHashMap<DeadGameObjInfo>, game_world: &mut GameWorldParams) {
+    let game_lib = game_world.game_lib.as_ref();
+
     for (e, dead_obj) in dead_objs.iter() {
-        map.remove_obj(&dead_obj.map_pos, e);
-        game_obj_lib.remove(e);
+        game_world.map.remove_obj(&dead_obj.map_pos, e);
+        let Some(obj) = game_world.game_obj_lib.remove(e) else {
+            continue;
+        };
         if !dead_obj.is_phasing {
-            despawn_pool.insert(e.clone());
+            game_world.despawn_pool.insert(*e);
         } else {
-            commands
-                .entity(e.clone())
+            game_world
+                .commands
+                .entity(*e)
                 .remove::<AIComponent>()
-                .insert(PhasingTimer::new(game_lib.get_game_config().phasing_duration));
+                .insert(PhasingComponent::new(
+                    game_lib.get_game_config().phasing_duration,
+                    &obj.pos,
+                    &obj.direction,
+                    obj.team,
+                ));
         }
     }
 }
 
-fn get_player_info_for_ai(
-    player_info: &PlayerInfo,
+// Spawn points reserved for the player come first, then shared ones, then
+// where the player was first placed. When all of them are blocked the player
+// comes back at the free nav cell nearest to the first of them.
+fn find_spawn_pos(
+    player_id: usize,
+    slot: &PlayerSlot,
+    nav_grid: &NavGrid,
+    game_world: &GameWorldParams,
+) -> Option<(Vec2, Vec2)> {
+    let map = game_world.map.as_ref();
+    let collide_span = game_world
+        .game_lib
+        .get_obj_config(slot.config_index)
+        .collide_span;
+    let mut spawn_points: Vec<_> = map
+        .spawn_points
+        .iter()
+        .filter(|p| p.player_id.is_none_or(|id| id == player_id))
+        .collect();
+    spawn_points.sort_by_key(|p| p.player_id.is_none());
+
+    let mut candidates: Vec<(Vec2, Vec2)> = spawn_points
+        .iter()
+        .map(|p| (arr_to_vec2(&p.pos), p.direction.into()))
+        .collect();
+    candidates.push((slot.spawn_pos, slot.spawn_direction));
+
+    let is_free = |pos: &Vec2| {
+        map.is_area_free(
+            pos,
+            collide_span,
+            game_world.game_obj_lib.as_ref(),
+            game_world.despawn_pool.as_ref(),
+        )
+    };
+    if let Some(candidate) = candidates.iter().find(|(pos, _)| is_free(pos)) {
+        return Some(*candidate);
+    }
+
+    let (pos, direction) = candidates[0];
+    nav_grid
+        .get_passable_by_distance(&pos, collide_span)
+        .iter()
+        .map(|map_pos| nav_grid.cell_center(map_pos))
+        .find(is_free)
+        .map(|pos| (pos, direction))
+}
+
+// Every tank outside brush is a potential target; each AI picks the nearest
+// enemy of its team
+fn get_targets_for_ai(
+    map: &GameMap,
     game_obj_lib: &GameObjInfoLib,
     game_lib: &GameLib,
-) -> Option<(Vec2, f32)> {
-    let Some(player_entity) = player_info.0.as_ref() else {
+) -> Vec<(Entity, Vec2, f32, usize)> {
+    let mut targets: Vec<_> = game_obj_lib
+        .iter()
+        .filter(|(_, obj)| {
+            obj.obj_type == GameObjType::Tank
+                && !map.is_on_terrain(&obj.pos, Terrain::Brush, game_obj_lib)
+        })
+        .map(|(entity, obj)| {
+            let collide_span = game_lib.get_obj_config(obj.config_index).collide_span;
+            (*entity, obj.pos, collide_span, obj.team)
+        })
+        .collect();
+    targets.sort_by_key(|(entity, ..)| *entity);
+    targets
+}
+
+fn get_nearest_enemy(
+    entity: &Entity,
+    obj: &GameObjInfo,
+    targets: &[(Entity, Vec2, f32, usize)],
+    game_lib: &GameLib,
+) -> Option<AITarget> {
+    targets
+        .iter()
+        .filter(|(e, _, _, team)| {
+            e != entity && game_lib.get_relation(obj.team, *team) == Relation::Enemy
+        })
+        .min_by(|(_, a, ..), (_, b, ..)| {
+            obj.pos
+                .distance_squared(*a)
+                .total_cmp(&obj.pos.distance_squared(*b))
+        })
+        .map(|(_, pos, collide_span, _)| AITarget {
+            pos: *pos,
+            collide_span: *collide_span,
+        })
+}
+
+fn get_obj_for_ai<'a, 'b>(
+    entity: &Entity,
+    game_obj_lib: &'a mut GameObjInfoLib,
+    game_lib: &'b GameLib,
+) -> Option<(&'a mut GameObjInfo, &'b AIConfig)> {
+    let Some(obj) = game_obj_lib.get_mut(entity) else {
+        error!("Failed to find tank {} in GameObjInfoLib", entity);
         return None;
     };
-    let Some((config_index, player_pos)) = game_obj_lib
-        .get(player_entity)
-        .map(|obj| (obj.config_index, obj.pos))
-    else {
-        error!("Failed to find player in GameObjInfoLib");
+    let ai_config_name = game_lib
+        .get_obj_config(obj.config_index)
+        .ai_config
+        .as_ref()?;
+    let Some(ai_config) = game_lib.get_ai_config(ai_config_name) else {
+        error!("Failed to find AIConfig {}", ai_config_name);
         return None;
     };
-    let player_collide_span = game_lib.get_obj_config(config_index).collide_span;
-
-    Some((player_pos, player_collide_span))
-}
-
-// fn get_obj_for_ai<'a, 'b>(
-//     entity: &Entity,
-//     game_obj_lib: &'a mut GameObjInfoLib,
-//     game_lib: &'b GameLib,
-// ) -> Option<(&'a mut GameObjInfo, &'b AIConfig)> {
-//     let Some(obj) = game_obj_lib.get_mut(&entity) else {
-//         error!("Failed to find tank {} in GameObjInfoLib", entity);
-//         return None;
-//     };
-//     let Some(ai_config_name) = game_lib.get_obj_config(obj.config_index).ai_config.as_ref() else {
-//         return None;
-//     };
-//     let Some(ai_config) = game_lib.config.ai_configs.get(ai_config_name) else {
-//         error!("Failed to find AIConfig {}", ai_config_name);
-//         return None;
-//     };
-
-//     Some((obj, ai_config))
-// }
+
+    Some((obj, ai_config))
+}
+
+fn has_clear_shot(
+    entity: &Entity,
+    obj: &GameObjInfo,
+    shoot_comp: &ShootComponent,
+    game_world: &GameWorldParams,
+) -> bool {
+    let map = game_world.map.as_ref();
+    let game_lib = game_world.game_lib.as_ref();
+    let game_obj_lib = game_world.game_obj_lib.as_ref();
+    let missile_config = game_lib.get_obj_config(shoot_comp.missile_config_index);
+    let end = shoot_comp.shoot_pos + obj.direction * (map.width + map.height);
+
+    map.cast_ray(
+        &shoot_comp.shoot_pos,
+        &end,
+        missile_config.collide_span,
+        game_obj_lib,
+        game_world.despawn_pool.as_ref(),
+        // Missiles pass through the shooter and its allies
+        |e, hit| e == entity || game_lib.get_relation(obj.team, hit.team) == Relation::Ally,
+    )
+    .and_then(|e| game_obj_lib.get(&e))
+    .is_some_and(|hit| {
+        (hit.obj_type == GameObjType::Tank || hit.obj_type == GameObjType::Base)
+            && game_lib.get_relation(obj.team, hit.team) == Relation::Enemy
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+    use std::time::Duration;
+
+    const CONFIG_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/game_config.json");
+
+    fn new_obj(config_name: &str, pos: [f32; 2]) -> GameMapObjConfig {
+        GameMapObjConfig::new(config_name.to_string(), pos, Direction::Up)
+    }
+
+    // Player 0 waits to respawn at `player_pos` with two lives left and steel
+    // tiles at `tile_positions`
+    fn new_world(player_pos: [f32; 2], tile_positions: &[[f32; 2]]) -> World {
+        let game_lib = GameLib::new(CONFIG_PATH).unwrap();
+        let mut objs = vec![GameMapObjConfig {
+            player_id: Some(0),
+            lives: Some(2),
+            respawn_elapsed: Some(game_lib.get_game_config().respawn_delay),
+            ..new_obj("player_tank", player_pos)
+        }];
+        objs.extend(tile_positions.iter().map(|pos| new_obj("steel_tile", *pos)));
+        let map_config = GameMapConfig {
+            objs,
+            spawn_points: vec![SpawnPointConfig {
+                name: "under_tile".to_string(),
+                pos: [420.0, 420.0],
+                direction: Direction::Down,
+                player_id: None,
+            }],
+            ..Default::default()
+        };
+
+        let mut world = World::new();
+        world.insert_resource(game_lib);
+        world.insert_resource(Time::<()>::default());
+        world
+            .run_system_once(move |game_lib: Res<GameLib>, mut commands: Commands| {
+                let mut game_obj_lib = GameObjInfoLib(EntityHashMap::new());
+                let map = GameMap::from_config(
+                    &map_config,
+                    game_lib.as_ref(),
+                    GameMode::Coop,
+                    &mut game_obj_lib,
+                    &mut commands,
+                );
+                insert_game_world(map, game_obj_lib, game_lib.as_ref(), &mut commands);
+            })
+            .unwrap();
+        world
+    }
+
+    fn respawn(world: &mut World) -> Option<Vec2> {
+        world
+            .resource_mut::<Time>()
+            .advance_by(Duration::from_secs_f32(0.1));
+        world.run_system_once(respawn_players).unwrap();
+
+        let slot = &world.resource::<PlayerInfo>()[&0];
+        assert!(slot.respawn_timer.is_none());
+        world
+            .resource::<GameObjInfoLib>()
+            .get(&slot.entity)
+            .map(|obj| obj.pos)
+    }
+
+    #[test]
+    fn blocked_spawn_point_falls_back_to_first_place() {
+        let mut world = new_world([100.0, 100.0], &[[420.0, 420.0]]);
+
+        assert_eq!(respawn(&mut world), Some(Vec2::new(100.0, 100.0)));
+    }
+
+    #[test]
+    fn blocked_spawn_places_fall_back_to_nearest_free_place() {
+        let mut world = new_world([100.0, 100.0], &[[420.0, 420.0], [100.0, 100.0]]);
+
+        let pos = respawn(&mut world).unwrap();
+        assert_eq!(world.resource::<PlayerInfo>()[&0].lives, 2);
+        assert!(pos.distance(Vec2::new(420.0, 420.0)) < 80.0, "{:?}", pos);
+        assert!(
+            world.resource::<GameMap>().is_area_free(
+                &pos,
+                30.0,
+                &GameObjInfoLib(
+                    world
+                        .resource::<GameObjInfoLib>()
+                        .iter()
+                        .filter(|(_, obj)| obj.obj_type == GameObjType::Tile)
+                        .map(|(e, obj)| (*e, *obj))
+                        .collect()
+                ),
+                world.resource::<DespawnPool>(),
+            )
+        );
+    }
+
+    #[test]
+    fn full_map_drops_the_life() {
+        let tile_positions: Vec<[f32; 2]> = (0..20)
+            .flat_map(|row| {
+                (0..20).map(move |col| [col as f32 * 40.0 + 20.0, row as f32 * 40.0 + 20.0])
+            })
+            .collect();
+        let mut world = new_world([100.0, 100.0], &tile_positions);
+
+        assert_eq!(respawn(&mut world), None);
+        let slot = &world.resource::<PlayerInfo>()[&0];
+        assert_eq!(slot.lives, 1);
+        assert!(!slot.is_in_game(world.resource::<GameObjInfoLib>()));
+    }
+}
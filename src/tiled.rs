@@ -0,0 +1,497 @@
+use crate::game_lib::*;
+use crate::game_map::*;
+use crate::my_error::*;
+use crate::utils::*;
+
+use bevy::prelude::*;
+use serde::Deserialize;
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+
+// Tiled keeps the flip and rotation flags in the top four bits of a gid
+const GID_MASK: u32 = 0x0fff_ffff;
+
+#[derive(Deserialize)]
+struct TiledMap {
+    width: usize,
+    height: usize,
+    tilewidth: f32,
+    tileheight: f32,
+    #[serde(default)]
+    infinite: bool,
+    layers: Vec<TiledLayer>,
+    #[serde(default)]
+    tilesets: Vec<TiledTilesetRef>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum TiledLayer {
+    TileLayer {
+        name: String,
+        width: usize,
+        #[serde(default)]
+        data: Option<TiledLayerData>,
+    },
+    ObjectGroup {
+        name: String,
+        objects: Vec<TiledObject>,
+    },
+    Group {
+        layers: Vec<TiledLayer>,
+    },
+    ImageLayer {},
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TiledLayerData {
+    Csv(Vec<u32>),
+    Encoded(IgnoredAny),
+}
+
+#[derive(Deserialize)]
+struct TiledTilesetRef {
+    firstgid: u32,
+    #[serde(default)]
+    source: Option<String>,
+    #[serde(default)]
+    tiles: Vec<TiledTile>,
+}
+
+#[derive(Deserialize)]
+struct TiledTileset {
+    #[serde(default)]
+    tiles: Vec<TiledTile>,
+}
+
+#[derive(Deserialize)]
+struct TiledTile {
+    id: u32,
+    #[serde(default)]
+    properties: Vec<TiledProperty>,
+}
+
+#[derive(Deserialize)]
+struct TiledObject {
+    id: u32,
+    #[serde(default)]
+    name: String,
+    #[serde(default, rename = "type", alias = "class")]
+    class: String,
+    x: f32,
+    y: f32,
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+    #[serde(default)]
+    gid: Option<u32>,
+    #[serde(default)]
+    properties: Vec<TiledProperty>,
+}
+
+#[derive(Deserialize)]
+struct TiledProperty {
+    name: String,
+    value: Value,
+}
+
+struct TiledTileInfo {
+    config_name: Option<String>,
+    direction: Option<Direction>,
+}
+
+/// Reads a map exported from the Tiled editor as JSON (.tmj).
+///
+/// Tiles carry a `config_name` property, and optionally a `direction`. Objects
+/// take their config name from a `config_name` property, their tile or their
+/// class. Objects with a `count` property become spawners, placed in the
+/// wave given by a `wave` property if there is one.
+pub fn load_tiled_map(map_path: &Path, game_config: &GameConfig) -> Result<GameMapConfig, MyError> {
+    convert_tiled_map(read_json(map_path)?, map_path, game_config)
+}
+
+fn convert_tiled_map(
+    tiled_map: TiledMap,
+    map_path: &Path,
+    game_config: &GameConfig,
+) -> Result<GameMapConfig, MyError> {
+    if tiled_map.infinite {
+        return Err(MyError::Map(
+            "Infinite Tiled maps are not supported".to_string(),
+        ));
+    }
+    if tiled_map.width > game_config.map_col_count()
+        || tiled_map.height > game_config.map_row_count()
+    {
+        return Err(MyError::Map(format!(
+            "Tiled map is {}x{} tiles, larger than the map's {}x{}",
+            tiled_map.width,
+            tiled_map.height,
+            game_config.map_col_count(),
+            game_config.map_row_count()
+        )));
+    }
+
+    let tiles = load_tilesets(&tiled_map.tilesets, map_path)?;
+    let mut loader = TiledLoader {
+        tiles,
+        cell_size: game_config.map_cell_size,
+        scale: Vec2::new(
+            game_config.map_cell_size / tiled_map.tilewidth,
+            game_config.map_cell_size / tiled_map.tileheight,
+        ),
+        map_height: game_config.map_row_count() as f32 * game_config.map_cell_size,
+        row_count: tiled_map.height,
+        col_count: tiled_map.width,
+        map_config: GameMapConfig::default(),
+    };
+
+    for layer in tiled_map.layers.iter() {
+        loader.load_layer(layer)?;
+    }
+
+    Ok(loader.map_config)
+}
+
+// A map smaller than the game's is placed at its top left, the same as a grid
+struct TiledLoader {
+    tiles: HashMap<u32, TiledTileInfo>,
+    cell_size: f32,
+    scale: Vec2,
+    map_height: f32,
+    // Size of the Tiled map in tiles
+    row_count: usize,
+    col_count: usize,
+    map_config: GameMapConfig,
+}
+
+impl TiledLoader {
+    fn load_layer(&mut self, layer: &TiledLayer) -> Result<(), MyError> {
+        match layer {
+            TiledLayer::TileLayer { name, width, data } => match data {
+                Some(TiledLayerData::Csv(gids)) => self.load_tile_layer(name, *width, gids),
+                Some(TiledLayerData::Encoded(_)) => Err(MyError::Map(format!(
+                    "Tile layer {} is encoded, save it with the CSV layer format",
+                    name
+                ))),
+                None => Ok(()),
+            },
+            TiledLayer::ObjectGroup { name, objects } => {
+                for object in objects.iter() {
+                    self.load_object(name, object)?;
+                }
+                Ok(())
+            }
+            TiledLayer::Group { layers } => {
+                for layer in layers.iter() {
+                    self.load_layer(layer)?;
+                }
+                Ok(())
+            }
+            TiledLayer::ImageLayer {} => Ok(()),
+        }
+    }
+
+    fn load_tile_layer(&mut self, name: &str, width: usize, gids: &[u32]) -> Result<(), MyError> {
+        if width != self.col_count || gids.len() != width * self.row_count {
+            return Err(MyError::Map(format!(
+                "Tile layer {} has {} tiles in rows of {}, the map is {}x{}",
+                name,
+                gids.len(),
+                width,
+                self.col_count,
+                self.row_count
+            )));
+        }
+
+        for (i, gid) in gids.iter().enumerate() {
+            let gid = gid & GID_MASK;
+            if gid == 0 {
+                continue;
+            }
+
+            let (row, col) = (i / width, i % width);
+            let tile = self.get_tile(gid).map_err(|err| {
+                MyError::Map(format!(
+                    "{} in layer {} at row {} column {}",
+                    err, name, row, col
+                ))
+            })?;
+            let Some(config_name) = tile.config_name.clone() else {
+                return Err(MyError::Map(format!(
+                    "Tile id {} in layer {} has no config_name property",
+                    gid, name
+                )));
+            };
+
+            let pos = Vec2::new(
+                (col as f32 + 0.5) * self.cell_size,
+                self.map_height - (row as f32 + 0.5) * self.cell_size,
+            );
+            self.map_config.objs.push(GameMapObjConfig {
+                origin: Some(GameMapObjOrigin::TiledTile {
+                    layer: name.to_string(),
+                    row,
+                    col,
+                }),
+                ..GameMapObjConfig::new(
+                    config_name,
+                    pos.to_array(),
+                    tile.direction.unwrap_or_default(),
+                )
+            });
+        }
+
+        Ok(())
+    }
+
+    fn load_object(&mut self, layer_name: &str, object: &TiledObject) -> Result<(), MyError> {
+        let context = || format!("object {} in layer {}", object.id, layer_name);
+        let tile = match object.gid {
+            Some(gid) => Some(
+                self.get_tile(gid & GID_MASK)
+                    .map_err(|err| MyError::Map(format!("{} for {}", err, context())))?,
+            ),
+            None => None,
+        };
+
+        let config_name = get_property::<String>(&object.properties, "config_name")?
+            .or_else(|| tile.and_then(|t| t.config_name.clone()))
+            .or_else(|| (!object.class.is_empty()).then(|| object.class.clone()))
+            .ok_or_else(|| MyError::Map(format!("No config name for {}", context())))?;
+        let direction = get_property::<Direction>(&object.properties, "direction")?
+            .or_else(|| tile.and_then(|t| t.direction))
+            .unwrap_or_default();
+        let team = get_property::<String>(&object.properties, "team")?;
+
+        // Tile objects are anchored at their bottom left corner, the others at
+        // their top left corner
+        let size = Vec2::new(object.width, object.height);
+        let center = match object.gid {
+            Some(_) => Vec2::new(object.x + size.x / 2.0, object.y - size.y / 2.0),
+            None => Vec2::new(object.x + size.x / 2.0, object.y + size.y / 2.0),
+        } * self.scale;
+        let pos = [center.x, self.map_height - center.y];
+
+        if let Some(count) = get_property::<u32>(&object.properties, "count")? {
+            let spawner = GameMapSpawnerConfig {
+                config_name,
+                pos,
+                direction,
+                count,
+                interval: get_property(&object.properties, "interval")?.unwrap_or(1.0),
+                max_alive: get_property(&object.properties, "max_alive")?.unwrap_or(1),
+                team,
+                spawned: None,
+                elapsed: None,
+            };
+
+            match get_property::<usize>(&object.properties, "wave")? {
+                Some(wave) => {
+                    let waves = &mut self.map_config.waves;
+                    if waves.len() <= wave {
+                        waves.resize_with(wave + 1, || GameMapWaveConfig {
+                            spawners: Vec::new(),
+                        });
+                    }
+                    waves[wave].spawners.push(spawner);
+                }
+                None => self.map_config.spawners.push(spawner),
+            }
+            return Ok(());
+        }
+
+        self.map_config.objs.push(GameMapObjConfig {
+            name: (!object.name.is_empty()).then(|| object.name.clone()),
+            player_id: get_property(&object.properties, "player_id")?,
+            team,
+            origin: Some(GameMapObjOrigin::TiledObject(object.id)),
+            ..GameMapObjConfig::new(config_name, pos, direction)
+        });
+
+        Ok(())
+    }
+
+    fn get_tile(&self, gid: u32) -> Result<&TiledTileInfo, String> {
+        self.tiles
+            .get(&gid)
+            .ok_or_else(|| format!("Unknown tile id {}", gid))
+    }
+}
+
+fn load_tilesets(
+    tileset_refs: &[TiledTilesetRef],
+    map_path: &Path,
+) -> Result<HashMap<u32, TiledTileInfo>, MyError> {
+    let mut tiles = HashMap::new();
+
+    for tileset_ref in tileset_refs.iter() {
+        let external;
+        let tileset_tiles = match tileset_ref.source.as_ref() {
+            Some(source) if source.ends_with(".tsx") => {
+                return Err(MyError::Map(format!(
+                    "Tileset {} is XML, export it as JSON (.tsj)",
+                    source
+                )));
+            }
+            Some(source) => {
+                let path = map_path.parent().unwrap_or(Path::new("")).join(source);
+                external = read_json::<TiledTileset, _>(path)?;
+                &external.tiles
+            }
+            None => &tileset_ref.tiles,
+        };
+
+        for tile in tileset_tiles.iter() {
+            tiles.insert(
+                tileset_ref.firstgid + tile.id,
+                TiledTileInfo {
+                    config_name: get_property(&tile.properties, "config_name")?,
+                    direction: get_property(&tile.properties, "direction")?,
+                },
+            );
+        }
+    }
+
+    Ok(tiles)
+}
+
+fn get_property<T: DeserializeOwned>(
+    properties: &[TiledProperty],
+    name: &str,
+) -> Result<Option<T>, MyError> {
+    let Some(property) = properties.iter().find(|p| p.name == name) else {
+        return Ok(None);
+    };
+
+    serde_json::from_value(property.value.clone())
+        .map(Some)
+        .map_err(|err| MyError::Map(format!("Invalid property {}: {}", name, err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAP_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/map_03.tmj");
+
+    fn load_sample(edit: impl FnOnce(&mut Value)) -> Result<GameMapConfig, MyError> {
+        let game_config: GameConfig = read_json(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/assets/game_config.json"
+        ))
+        .unwrap();
+        let mut value: Value = read_json(MAP_PATH).unwrap();
+        edit(&mut value);
+        let tiled_map: TiledMap = serde_json::from_value(value).unwrap();
+        convert_tiled_map(tiled_map, Path::new(MAP_PATH), &game_config)
+    }
+
+    fn find_obj(map_config: &GameMapConfig, pos: [f32; 2]) -> &GameMapObjConfig {
+        map_config
+            .objs
+            .iter()
+            .find(|obj| obj.pos == pos)
+            .unwrap_or_else(|| panic!("No obj at {:?}", pos))
+    }
+
+    #[test]
+    fn tiles_become_objs_at_cell_centers() {
+        let map_config = load_sample(|_| ()).unwrap();
+
+        assert_eq!(
+            find_obj(&map_config, [20.0, 20.0]).config_name,
+            "steel_tile"
+        );
+        // The top right tile is flipped, which sets flags above the gid
+        let brick = find_obj(&map_config, [780.0, 780.0]);
+        assert_eq!(brick.config_name, "brick_tile");
+        assert_eq!(brick.direction, Direction::Up);
+    }
+
+    #[test]
+    fn all_flag_bits_are_masked() {
+        let map_config =
+            load_sample(|value| value["layers"][0]["data"][0] = (0x1000_0000u32 | 1).into())
+                .unwrap();
+
+        assert_eq!(
+            find_obj(&map_config, [20.0, 780.0]).config_name,
+            "steel_tile"
+        );
+    }
+
+    #[test]
+    fn objects_take_tile_and_properties() {
+        let map_config = load_sample(|_| ()).unwrap();
+
+        // Tile objects are anchored at their bottom left corner, scaled from
+        // 32 pixel tiles to 40 pixel cells
+        let player = find_obj(&map_config, [60.0, 740.0]);
+        assert_eq!(player.config_name, "player_tank");
+        assert_eq!(player.player_id, Some(0));
+        assert_eq!(player.direction, Direction::Up);
+
+        let ai_tank = find_obj(&map_config, [740.0, 700.0]);
+        assert_eq!(ai_tank.config_name, "ai_tank");
+        assert_eq!(ai_tank.direction, Direction::Left);
+    }
+
+    #[test]
+    fn objects_with_count_become_spawners() {
+        let map_config = load_sample(|_| ()).unwrap();
+
+        assert!(map_config.spawners.is_empty());
+        assert_eq!(map_config.waves.len(), 1);
+        let spawner = &map_config.waves[0].spawners[0];
+        assert_eq!(spawner.config_name, "ai_tank");
+        assert_eq!(spawner.pos, [420.0, 580.0]);
+        assert_eq!(spawner.count, 3);
+        assert_eq!(spawner.interval, 2.0);
+        assert_eq!(spawner.max_alive, 1);
+    }
+
+    #[test]
+    fn shorter_map_is_placed_at_the_top() {
+        let map_config = load_sample(|value| {
+            value["height"] = 10.into();
+            value["layers"][0]["data"]
+                .as_array_mut()
+                .unwrap()
+                .truncate(200);
+        })
+        .unwrap();
+
+        assert_eq!(
+            find_obj(&map_config, [780.0, 780.0]).config_name,
+            "brick_tile"
+        );
+        assert_eq!(
+            find_obj(&map_config, [60.0, 740.0]).config_name,
+            "player_tank"
+        );
+    }
+
+    #[test]
+    fn unknown_tile_id_is_an_error() {
+        let result = load_sample(|value| value["layers"][0]["data"][0] = 9.into());
+
+        let Err(MyError::Map(message)) = result else {
+            panic!("Expected a map error");
+        };
+        assert!(message.contains("Unknown tile id 9"), "{}", message);
+    }
+
+    #[test]
+    fn tile_layer_of_wrong_size_is_an_error() {
+        let result = load_sample(|value| {
+            value["layers"][0]["data"].as_array_mut().unwrap().pop();
+        });
+
+        assert!(matches!(result, Err(MyError::Map(_))));
+    }
+}
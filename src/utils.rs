@@ -1,10 +1,11 @@
+use crate::game_state::*;
+use crate::grid_map::*;
 use crate::my_error::*;
 
 use bevy::prelude::*;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use core::f32;
 use serde::de::DeserializeOwned;
-use serde_json;
 use std::{
     fs::File,
     io::BufReader,
@@ -13,16 +14,74 @@ use std::{
 use tracing_appender::non_blocking::WorkerGuard;
 use tracing_subscriber::{EnvFilter, fmt, prelude::*};
 
+// Subcommands take their own arguments, so the game's are only required
+// without one
 #[derive(Parser, Resource)]
+#[command(subcommand_negates_reqs = true)]
 pub struct Args {
-    #[arg(short, long)]
-    pub log_path: PathBuf,
+    #[command(subcommand)]
+    pub command: Option<Command>,
 
-    #[arg(short, long)]
-    pub config_path: PathBuf,
+    #[arg(short, long, required = true)]
+    pub log_path: Option<PathBuf>,
 
-    #[arg(short, long)]
-    pub map_path: PathBuf,
+    #[arg(short, long, required_unless_present = "replay_path")]
+    pub config_path: Option<PathBuf>,
+
+    #[arg(short, long, required_unless_present = "replay_path")]
+    pub map_path: Option<PathBuf>,
+
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Opens the map in the editor instead of playing it
+    #[arg(long, conflicts_with_all = ["headless", "replay_path", "record_path"])]
+    pub edit: bool,
+
+    #[arg(long, value_enum, default_value = "coop")]
+    pub mode: GameMode,
+
+    #[arg(long)]
+    pub max_ticks: Option<u64>,
+
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    #[arg(long = "record", conflicts_with = "replay_path")]
+    pub record_path: Option<PathBuf>,
+
+    #[arg(long = "replay")]
+    pub replay_path: Option<PathBuf>,
+
+    #[arg(long, default_value = "quicksave.json")]
+    pub save_path: PathBuf,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Converts the map between the grid and object list formats
+    Convert {
+        #[arg(short, long)]
+        config_path: PathBuf,
+
+        #[arg(short, long)]
+        map_path: PathBuf,
+
+        #[arg(long, value_enum)]
+        to: MapFormat,
+
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+
+    /// Lists the problems found in the map and fails if there are any
+    Validate {
+        #[arg(short, long)]
+        config_path: PathBuf,
+
+        #[arg(short, long)]
+        map_path: PathBuf,
+    },
 }
 
 pub fn read_json<T, P>(path: P) -> Result<T, MyError>
@@ -64,7 +123,7 @@ pub fn arr_to_vec2(v: &[f32; 2]) -> Vec2 {
 #[inline]
 pub fn get_rotation(d: &Vec2) -> Quat {
     let from = Vec2::new(1.0, 0.0);
-    Quat::from_rotation_arc_2d(from, d.clone())
+    Quat::from_rotation_arc_2d(from, *d)
 }
 
 pub fn check_collide_bounds_nonpass(
@@ -94,7 +153,7 @@ pub fn check_collide_bounds_nonpass(
         0.0
     };
 
-    let mut corrected_pos = pos.clone();
+    let mut corrected_pos = *pos;
     let min_x = collide_span;
     let max_x = width - collide_span;
     let min_y = collide_span;
@@ -140,7 +199,7 @@ pub fn check_collide_obj_nonpass(
     let total_span = collide_span1 + collide_span2;
     let dx = (pos1.x - pos2.x).abs();
     let dy = (pos1.y - pos2.y).abs();
-    let mut corrected_pos = pos1.clone();
+    let mut corrected_pos = *pos1;
 
     if dx >= total_span || dy >= total_span {
         return (false, corrected_pos);
@@ -181,3 +240,95 @@ pub fn check_collide_obj_pass(
     let total_span = collide_span1 + collide_span2;
     (pos1.x - pos2.x).abs() < total_span && (pos1.y - pos2.y).abs() < total_span
 }
+
+pub fn check_collide_segment_obj(
+    start: &Vec2,
+    end: &Vec2,
+    pos: &Vec2,
+    collide_span: f32,
+) -> Option<f32> {
+    let delta = end - start;
+    let mut t_enter = 0.0_f32;
+    let mut t_exit = 1.0_f32;
+
+    for (s, d, c) in [(start.x, delta.x, pos.x), (start.y, delta.y, pos.y)] {
+        let low = c - collide_span;
+        let high = c + collide_span;
+
+        if d == 0.0 {
+            if s <= low || s >= high {
+                return None;
+            }
+            continue;
+        }
+
+        let t1 = (low - s) / d;
+        let t2 = (high - s) / d;
+        t_enter = t_enter.max(t1.min(t2));
+        t_exit = t_exit.min(t1.max(t2));
+        if t_enter >= t_exit {
+            return None;
+        }
+    }
+
+    Some(t_enter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POS: Vec2 = Vec2::new(5.0, 5.0);
+
+    #[test]
+    fn segment_hits_obj_along_an_axis() {
+        let t = check_collide_segment_obj(&Vec2::new(0.0, 5.0), &Vec2::new(10.0, 5.0), &POS, 1.0);
+        assert_eq!(t, Some(0.4));
+
+        let t = check_collide_segment_obj(&Vec2::new(5.0, 10.0), &Vec2::new(5.0, 0.0), &POS, 1.0);
+        assert_eq!(t, Some(0.4));
+    }
+
+    #[test]
+    fn segment_along_an_edge_misses_obj() {
+        let t = check_collide_segment_obj(&Vec2::new(0.0, 6.0), &Vec2::new(10.0, 6.0), &POS, 1.0);
+        assert_eq!(t, None);
+
+        let t = check_collide_segment_obj(&Vec2::new(0.0, 7.0), &Vec2::new(10.0, 7.0), &POS, 1.0);
+        assert_eq!(t, None);
+    }
+
+    #[test]
+    fn segment_hits_obj_diagonally() {
+        let t = check_collide_segment_obj(&Vec2::ZERO, &Vec2::new(10.0, 10.0), &POS, 1.0);
+        assert_eq!(t, Some(0.4));
+    }
+
+    #[test]
+    fn segment_ending_on_an_edge_misses_obj() {
+        let t = check_collide_segment_obj(&Vec2::new(0.0, 5.0), &Vec2::new(4.0, 5.0), &POS, 1.0);
+        assert_eq!(t, None);
+
+        let t = check_collide_segment_obj(&Vec2::new(0.0, 5.0), &Vec2::new(4.5, 5.0), &POS, 1.0);
+        assert_eq!(t, Some(8.0 / 9.0));
+    }
+
+    #[test]
+    fn segment_grazing_a_corner_misses_obj() {
+        // Passes through the corner at (4, 6)
+        let t = check_collide_segment_obj(&Vec2::new(2.0, 4.0), &Vec2::new(6.0, 8.0), &POS, 1.0);
+        assert_eq!(t, None);
+
+        let t = check_collide_segment_obj(&Vec2::new(2.5, 4.0), &Vec2::new(6.5, 8.0), &POS, 1.0);
+        assert!(t.is_some());
+    }
+
+    #[test]
+    fn zero_length_segment_hits_only_inside_obj() {
+        let t = check_collide_segment_obj(&Vec2::new(5.5, 5.0), &Vec2::new(5.5, 5.0), &POS, 1.0);
+        assert_eq!(t, Some(0.0));
+
+        let t = check_collide_segment_obj(&Vec2::new(7.0, 5.0), &Vec2::new(7.0, 5.0), &POS, 1.0);
+        assert_eq!(t, None);
+    }
+}
@@ -0,0 +1,585 @@
+use crate::game_lib::*;
+use crate::game_map::*;
+use crate::my_error::*;
+use crate::pathfinding::*;
+use crate::utils::*;
+
+use bevy::prelude::*;
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MapProblemSource {
+    Map,
+    Obj(GameMapObjOrigin),
+    Spawner(usize),
+    SpawnPoint(usize),
+}
+
+#[derive(Debug)]
+pub struct MapProblem {
+    pub source: MapProblemSource,
+    pub message: String,
+}
+
+impl fmt::Display for MapProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.source {
+            MapProblemSource::Map => write!(f, "map: {}", self.message),
+            MapProblemSource::Obj(origin) => write!(f, "{}: {}", origin, self.message),
+            MapProblemSource::Spawner(index) => write!(f, "spawner {}: {}", index, self.message),
+            MapProblemSource::SpawnPoint(index) => {
+                write!(f, "spawn point {}: {}", index, self.message)
+            }
+        }
+    }
+}
+
+struct MapValidator<'a> {
+    map_config: &'a GameMapConfig,
+    game_lib: &'a GameLib,
+    map: GameMap,
+    problems: Vec<MapProblem>,
+}
+
+pub fn validate_map_file<P: AsRef<Path>>(
+    config_path: P,
+    map_path: P,
+) -> Result<Vec<MapProblem>, MyError> {
+    let game_lib = GameLib::new(config_path)?;
+    let map_config = GameMapConfig::read(map_path, game_lib.get_game_config())?;
+    Ok(validate_map(&map_config, &game_lib))
+}
+
+/// Lists every problem found in the map, ordered by the object they concern.
+pub fn validate_map(map_config: &GameMapConfig, game_lib: &GameLib) -> Vec<MapProblem> {
+    let game_config = game_lib.get_game_config();
+    let mut validator = MapValidator {
+        map_config,
+        game_lib,
+        map: GameMap::new(
+            game_config.map_cell_size,
+            game_config.map_row_count(),
+            game_config.map_col_count(),
+        ),
+        problems: Vec::new(),
+    };
+
+    validator.check_objs();
+    validator.check_overlaps();
+    validator.check_spawners();
+    validator.check_spawn_points();
+    validator.check_reachability();
+
+    let mut problems = validator.problems;
+    problems.sort_by(|problem, other| problem.source.cmp(&other.source));
+    problems
+}
+
+impl MapValidator<'_> {
+    fn add_problem(&mut self, source: MapProblemSource, message: String) {
+        self.problems.push(MapProblem { source, message });
+    }
+
+    fn check_objs(&mut self) {
+        for (index, obj) in self.map_config.objs.iter().enumerate() {
+            let Some(config_index) = self.game_lib.get_obj_config_index(&obj.config_name) else {
+                self.add_problem(
+                    get_obj_source(index, obj),
+                    format!("Unknown config name {}", obj.config_name),
+                );
+                continue;
+            };
+
+            let obj_config = self.game_lib.get_obj_config(config_index);
+            if !self
+                .map
+                .is_inside(&arr_to_vec2(&obj.pos), obj_config.collide_span)
+            {
+                self.add_problem(
+                    get_obj_source(index, obj),
+                    format!(
+                        "{} at {:?} does not fit inside the map",
+                        obj.config_name, obj.pos
+                    ),
+                );
+            }
+        }
+
+        if self.get_player_objs().next().is_none() {
+            self.add_problem(
+                MapProblemSource::Map,
+                "No player tank is placed in the map".to_string(),
+            );
+        }
+    }
+
+    // Only objects that block tanks may not overlap, so tanks can sit in brush
+    // or on ice
+    fn check_overlaps(&mut self) {
+        let solid_objs = self.get_solid_objs();
+        for (i, (origin, pos, collide_span)) in solid_objs.iter().enumerate() {
+            for (other_origin, other_pos, other_collide_span) in solid_objs[..i].iter() {
+                if check_collide_obj_pass(pos, *collide_span, other_pos, *other_collide_span) {
+                    self.add_problem(
+                        MapProblemSource::Obj(origin.clone()),
+                        format!("Overlaps {}", other_origin),
+                    );
+                }
+            }
+        }
+    }
+
+    // A spawn point must fit the largest tank of the players that may use it
+    fn check_spawn_points(&mut self) {
+        let solid_objs = self.get_solid_objs();
+        for (index, spawn_point) in self.map_config.spawn_points.iter().enumerate() {
+            let Some(collide_span) = self
+                .get_player_objs()
+                .filter(|(_, obj)| {
+                    spawn_point
+                        .player_id
+                        .is_none_or(|player_id| obj.player_id.unwrap_or_default() == player_id)
+                })
+                .filter_map(|(_, obj)| self.game_lib.get_obj_config_index(&obj.config_name))
+                .map(|config_index| self.game_lib.get_obj_config(config_index).collide_span)
+                .reduce(f32::max)
+            else {
+                continue;
+            };
+
+            let pos = arr_to_vec2(&spawn_point.pos);
+            if !self.map.is_inside(&pos, collide_span) {
+                self.add_problem(
+                    MapProblemSource::SpawnPoint(index),
+                    format!("Position {:?} does not fit inside the map", spawn_point.pos),
+                );
+            } else if let Some((origin, _, _)) =
+                solid_objs.iter().find(|(_, obj_pos, obj_collide_span)| {
+                    check_collide_obj_pass(&pos, collide_span, obj_pos, *obj_collide_span)
+                })
+            {
+                self.add_problem(
+                    MapProblemSource::SpawnPoint(index),
+                    format!("Overlaps {}", origin),
+                );
+            }
+        }
+    }
+
+    fn check_spawners(&mut self) {
+        for (index, spawner) in get_spawners(self.map_config).enumerate() {
+            if let Some(problem) = spawner.get_problem() {
+                self.add_problem(MapProblemSource::Spawner(index), problem);
+            }
+
+            let Some(config_index) = self.game_lib.get_obj_config_index(&spawner.config_name)
+            else {
+                self.add_problem(
+                    MapProblemSource::Spawner(index),
+                    format!("Unknown config name {}", spawner.config_name),
+                );
+                continue;
+            };
+
+            let obj_config = self.game_lib.get_obj_config(config_index);
+            if !self
+                .map
+                .is_inside(&arr_to_vec2(&spawner.pos), obj_config.collide_span)
+            {
+                self.add_problem(
+                    MapProblemSource::Spawner(index),
+                    format!("Position {:?} does not fit inside the map", spawner.pos),
+                );
+            }
+        }
+    }
+
+    // Floods the nav grid from the player tanks and reports the open areas and
+    // the tanks, spawners and spawn points that no player can get to
+    fn check_reachability(&mut self) {
+        let mut player_positions = Vec::new();
+        let mut collide_span = f32::MAX;
+        for (_, obj) in self.get_player_objs() {
+            let pos = arr_to_vec2(&obj.pos);
+            if let Some(config_index) = self.game_lib.get_obj_config_index(&obj.config_name)
+                && self.map.is_inside(&pos, 0.0)
+            {
+                player_positions.push(pos);
+                collide_span =
+                    collide_span.min(self.game_lib.get_obj_config(config_index).collide_span);
+            }
+        }
+        if player_positions.is_empty() {
+            return;
+        }
+
+        let nav_grid = self.build_nav_grid();
+        let starts: Vec<MapPos> = player_positions
+            .iter()
+            .flat_map(|pos| nav_grid.get_passable_around(pos, collide_span))
+            .collect();
+        let reached = nav_grid.get_reachable(&starts, collide_span);
+        // Bodies that do not fit inside the map are reported by the other checks
+        let is_reached = |pos: &[f32; 2], body_span: f32| {
+            let pos = arr_to_vec2(pos);
+            !self.map.is_inside(&pos, body_span)
+                || nav_grid
+                    .get_passable_around(&pos, collide_span)
+                    .iter()
+                    .any(|map_pos| reached[map_pos.row][map_pos.col])
+        };
+
+        let mut problems = Vec::new();
+        for (index, obj) in self.map_config.objs.iter().enumerate() {
+            let Some(config_index) = self.game_lib.get_obj_config_index(&obj.config_name) else {
+                continue;
+            };
+            let obj_config = self.game_lib.get_obj_config(config_index);
+            if obj_config.obj_type == GameObjType::Tank
+                && !is_reached(&obj.pos, obj_config.collide_span)
+            {
+                problems.push((
+                    get_obj_source(index, obj),
+                    format!("{} cannot be reached by any player", obj.config_name),
+                ));
+            }
+        }
+        for (index, spawner) in get_spawners(self.map_config).enumerate() {
+            let Some(config_index) = self.game_lib.get_obj_config_index(&spawner.config_name)
+            else {
+                continue;
+            };
+            if !is_reached(
+                &spawner.pos,
+                self.game_lib.get_obj_config(config_index).collide_span,
+            ) {
+                problems.push((
+                    MapProblemSource::Spawner(index),
+                    "Cannot be reached by any player".to_string(),
+                ));
+            }
+        }
+        for (index, spawn_point) in self.map_config.spawn_points.iter().enumerate() {
+            if !is_reached(&spawn_point.pos, collide_span) {
+                problems.push((
+                    MapProblemSource::SpawnPoint(index),
+                    "Cannot be reached by any player".to_string(),
+                ));
+            }
+        }
+
+        // Every open area left over is reported once, by its first cell
+        let mut seen = reached.clone();
+        for row in 0..nav_grid.row_count() {
+            for col in 0..nav_grid.col_count() {
+                let map_pos = MapPos { row, col };
+                if seen[row][col] || !nav_grid.is_passable(&map_pos, collide_span) {
+                    continue;
+                }
+
+                let area = nav_grid.get_reachable(&[map_pos], collide_span);
+                let mut cell_count = 0;
+                for (seen_row, area_row) in seen.iter_mut().zip(area.iter()) {
+                    for (seen_cell, in_area) in seen_row.iter_mut().zip(area_row.iter()) {
+                        if *in_area {
+                            *seen_cell = true;
+                            cell_count += 1;
+                        }
+                    }
+                }
+                problems.push((
+                    MapProblemSource::Map,
+                    format!(
+                        "Area of {} nav cells around {:?} cannot be reached by any player",
+                        cell_count,
+                        nav_grid.cell_center(&map_pos).to_array()
+                    ),
+                ));
+            }
+        }
+
+        for (source, message) in problems {
+            self.add_problem(source, message);
+        }
+    }
+
+    // Objects that tanks cannot pass, other than players waiting to respawn
+    fn get_solid_objs(&self) -> Vec<(GameMapObjOrigin, Vec2, f32)> {
+        self.map_config
+            .objs
+            .iter()
+            .enumerate()
+            .filter(|(_, obj)| obj.respawn_elapsed.is_none())
+            .filter_map(|(index, obj)| {
+                let config_index = self.game_lib.get_obj_config_index(&obj.config_name)?;
+                let obj_config = self.game_lib.get_obj_config(config_index);
+                (obj_config.obj_type.is_solid() && obj_config.terrain.blocks_tanks()).then(|| {
+                    (
+                        get_obj_origin(index, obj),
+                        arr_to_vec2(&obj.pos),
+                        obj_config.collide_span,
+                    )
+                })
+            })
+            .collect()
+    }
+
+    fn build_nav_grid(&self) -> NavGrid {
+        let mut nav_grid = NavGrid::from_map(&self.map, self.game_lib.get_max_tank_collide_span());
+
+        for obj in self.map_config.objs.iter() {
+            let Some(config_index) = self.game_lib.get_obj_config_index(&obj.config_name) else {
+                continue;
+            };
+            let obj_config = self.game_lib.get_obj_config(config_index);
+            if obj_config.obj_type.is_obstacle()
+                && obj_config.terrain.blocks_tanks()
+                && obj_config.collide_span > 0.0
+            {
+                nav_grid.add_obstacle(&arr_to_vec2(&obj.pos), obj_config.collide_span);
+            }
+        }
+
+        nav_grid
+    }
+
+    // Tanks without an AI config are driven by a player
+    fn get_player_objs(&self) -> impl Iterator<Item = (usize, &GameMapObjConfig)> {
+        self.map_config.objs.iter().enumerate().filter(|(_, obj)| {
+            self.game_lib
+                .get_obj_config_index(&obj.config_name)
+                .map(|config_index| self.game_lib.get_obj_config(config_index))
+                .is_some_and(|obj_config| {
+                    obj_config.obj_type == GameObjType::Tank && obj_config.ai_config.is_none()
+                })
+        })
+    }
+}
+
+// Objects made in code rather than read from a file have no origin, so the
+// index in the object list is the best there is
+fn get_obj_origin(index: usize, obj: &GameMapObjConfig) -> GameMapObjOrigin {
+    obj.origin.clone().unwrap_or(GameMapObjOrigin::Objs(index))
+}
+
+fn get_obj_source(index: usize, obj: &GameMapObjConfig) -> MapProblemSource {
+    MapProblemSource::Obj(get_obj_origin(index, obj))
+}
+
+// Same order as the spawner indices in saved maps
+fn get_spawners(map_config: &GameMapConfig) -> impl Iterator<Item = &GameMapSpawnerConfig> {
+    map_config.spawners.iter().chain(
+        map_config
+            .waves
+            .iter()
+            .flat_map(|wave| wave.spawners.iter()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/game_config.json");
+
+    fn validate(objs: Vec<GameMapObjConfig>) -> Vec<MapProblem> {
+        let game_lib = GameLib::new(CONFIG_PATH).unwrap();
+        let map_config = GameMapConfig {
+            objs,
+            ..Default::default()
+        };
+        validate_map(&map_config, &game_lib)
+    }
+
+    fn new_obj(config_name: &str, pos: [f32; 2]) -> GameMapObjConfig {
+        GameMapObjConfig::new(config_name.to_string(), pos, Direction::Up)
+    }
+
+    fn find_problem(problems: &[MapProblem], index: usize) -> &MapProblem {
+        problems
+            .iter()
+            .find(|problem| problem.source == MapProblemSource::Obj(GameMapObjOrigin::Objs(index)))
+            .unwrap_or_else(|| panic!("No problem with obj {} in {:?}", index, problems))
+    }
+
+    #[test]
+    fn shipped_maps_have_no_problems() {
+        for map_name in ["map_01.json", "map_02.json", "map_03.tmj"] {
+            let map_path = format!("{}/assets/{}", env!("CARGO_MANIFEST_DIR"), map_name);
+            let problems = validate_map_file(CONFIG_PATH, map_path.as_str()).unwrap();
+            assert!(problems.is_empty(), "{}: {:?}", map_name, problems);
+        }
+    }
+
+    // Tanks resting against the border and against a wall, and a 2-tile gap
+    // in the wall that is just wide enough for them
+    #[test]
+    fn clean_map_has_no_problems() {
+        let mut objs = vec![
+            new_obj("player_tank", [30.0, 30.0]),
+            new_obj("ai_tank", [370.0, 200.0]),
+            new_obj("ai_tank", [770.0, 770.0]),
+        ];
+        objs.extend(
+            (0..20)
+                .filter(|row| *row != 9 && *row != 10)
+                .map(|row| new_obj("steel_tile", [420.0, row as f32 * 40.0 + 20.0])),
+        );
+
+        let problems = validate(objs);
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn overlapping_objs_are_reported() {
+        let problems = validate(vec![
+            new_obj("player_tank", [100.0, 100.0]),
+            new_obj("steel_tile", [300.0, 300.0]),
+            new_obj("steel_tile", [310.0, 300.0]),
+        ]);
+
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+        assert_eq!(find_problem(&problems, 2).message, "Overlaps obj 1");
+    }
+
+    #[test]
+    fn objs_outside_the_map_are_reported() {
+        let problems = validate(vec![
+            new_obj("player_tank", [100.0, 100.0]),
+            new_obj("steel_tile", [10.0, 300.0]),
+            new_obj("ai_tank", [600.0, 790.0]),
+        ]);
+
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(find_problem(&problems, 1).message.contains("does not fit"));
+        assert!(find_problem(&problems, 2).message.contains("does not fit"));
+    }
+
+    #[test]
+    fn unreachable_tanks_are_reported() {
+        // Walls from the borders around the bottom left corner close the
+        // player in
+        let mut objs = vec![
+            new_obj("player_tank", [80.0, 80.0]),
+            new_obj("ai_tank", [600.0, 600.0]),
+        ];
+        for i in 0..5 {
+            let s = i as f32 * 40.0 + 20.0;
+            objs.push(new_obj("steel_tile", [180.0, s]));
+            objs.push(new_obj("steel_tile", [s, 180.0]));
+        }
+        objs.push(new_obj("steel_tile", [180.0, 180.0]));
+
+        let problems = validate(objs);
+        assert!(
+            find_problem(&problems, 1)
+                .message
+                .contains("cannot be reached")
+        );
+        assert!(
+            problems
+                .iter()
+                .any(|problem| problem.source == MapProblemSource::Map)
+        );
+    }
+
+    #[test]
+    fn map_without_player_is_reported() {
+        let problems = validate(vec![new_obj("ai_tank", [100.0, 100.0])]);
+
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+        assert_eq!(problems[0].source, MapProblemSource::Map);
+    }
+
+    #[test]
+    fn spawners_that_never_finish_are_reported() {
+        let new_spawner = |count, interval, max_alive| GameMapSpawnerConfig {
+            config_name: "ai_tank".to_string(),
+            pos: [600.0, 600.0],
+            direction: Direction::Down,
+            count,
+            interval,
+            max_alive,
+            team: None,
+            spawned: None,
+            elapsed: None,
+        };
+        let game_lib = GameLib::new(CONFIG_PATH).unwrap();
+        let map_config = GameMapConfig {
+            objs: vec![new_obj("player_tank", [100.0, 100.0])],
+            spawners: vec![
+                new_spawner(3, 2.0, 2),
+                new_spawner(0, 2.0, 2),
+                new_spawner(3, 2.0, 0),
+            ],
+            waves: vec![GameMapWaveConfig {
+                spawners: vec![new_spawner(3, 0.0, 2), new_spawner(3, -1.0, 2)],
+            }],
+            ..Default::default()
+        };
+
+        let problems = validate_map(&map_config, &game_lib);
+        let sources: Vec<&MapProblemSource> =
+            problems.iter().map(|problem| &problem.source).collect();
+        assert_eq!(
+            sources,
+            [1, 2, 3, 4]
+                .map(MapProblemSource::Spawner)
+                .iter()
+                .collect::<Vec<_>>(),
+            "{:?}",
+            problems
+        );
+        assert!(problems[1].message.contains("never finishes"));
+        assert!(problems[2].message.contains("not positive"));
+    }
+
+    fn validate_spawn_points(spawn_points: &[[f32; 2]]) -> Vec<MapProblem> {
+        let game_lib = GameLib::new(CONFIG_PATH).unwrap();
+        let map_config = GameMapConfig {
+            objs: vec![
+                new_obj("player_tank", [100.0, 100.0]),
+                new_obj("steel_tile", [300.0, 300.0]),
+            ],
+            spawn_points: spawn_points
+                .iter()
+                .enumerate()
+                .map(|(index, pos)| SpawnPointConfig {
+                    name: format!("spawn_point_{}", index),
+                    pos: *pos,
+                    direction: Direction::Up,
+                    player_id: None,
+                })
+                .collect(),
+            ..Default::default()
+        };
+        validate_map(&map_config, &game_lib)
+    }
+
+    #[test]
+    fn spawn_points_outside_the_map_are_reported() {
+        // The first one fits a tile but not the player tank
+        let problems = validate_spawn_points(&[[20.0, 400.0], [400.0, 400.0]]);
+
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+        assert_eq!(problems[0].source, MapProblemSource::SpawnPoint(0));
+        assert!(problems[0].message.contains("does not fit"));
+    }
+
+    #[test]
+    fn spawn_points_on_solid_objs_are_reported() {
+        let problems = validate_spawn_points(&[[400.0, 400.0], [320.0, 320.0]]);
+
+        let problem = problems
+            .iter()
+            .find(|problem| problem.source == MapProblemSource::SpawnPoint(1))
+            .unwrap_or_else(|| panic!("No problem with spawn point 1 in {:?}", problems));
+        assert_eq!(problem.message, "Overlaps obj 1");
+        assert!(
+            problems
+                .iter()
+                .all(|problem| problem.source != MapProblemSource::SpawnPoint(0))
+        );
+    }
+}
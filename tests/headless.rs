@@ -0,0 +1,182 @@
+use bevy::ecs::system::RunSystemOnce;
+use bevy::prelude::*;
+use clap::Parser;
+use serde_json::json;
+use tank_rs::create_app;
+use tank_rs::game_map::*;
+use tank_rs::game_obj::*;
+use tank_rs::game_state::*;
+use tank_rs::save::*;
+use tank_rs::simulation::*;
+use tank_rs::systems::*;
+use tank_rs::utils::*;
+
+const MAX_TICKS: u64 = 300;
+
+fn create_headless_app(seed: u64) -> App {
+    let log_path = std::env::temp_dir().join("tank_rs_test.log");
+    let args = Args::parse_from([
+        "tank_rs",
+        "-l",
+        log_path.to_str().unwrap(),
+        "-c",
+        concat!(env!("CARGO_MANIFEST_DIR"), "/assets/game_config.json"),
+        "-m",
+        concat!(env!("CARGO_MANIFEST_DIR"), "/assets/map_01.json"),
+        "--headless",
+        "--max-ticks",
+        &MAX_TICKS.to_string(),
+        "--seed",
+        &seed.to_string(),
+    ]);
+
+    let mut app = create_app(args);
+    app.finish();
+    app.cleanup();
+    app
+}
+
+fn run_headless(seed: u64) -> App {
+    let mut app = create_headless_app(seed);
+    while app.should_exit().is_none() {
+        app.update();
+    }
+    assert_eq!(app.should_exit(), Some(AppExit::Success));
+
+    app
+}
+
+#[test]
+fn headless_run_exits_within_max_ticks() {
+    let app = run_headless(3);
+
+    let sim_tick = app.world().resource::<SimTick>().0;
+    assert!(sim_tick > 0 && sim_tick <= MAX_TICKS);
+    assert!(!app.world().resource::<GameObjInfoLib>().is_empty());
+}
+
+#[test]
+fn same_seed_gives_same_state() {
+    let digests: Vec<u64> = (0..2)
+        .map(|_| {
+            let app = run_headless(7);
+            get_state_digest(app.world().resource::<GameObjInfoLib>())
+        })
+        .collect();
+
+    assert_eq!(digests[0], digests[1]);
+}
+
+#[test]
+fn saved_game_loads_back_unchanged() {
+    let saved = json!({
+        "objs": [
+            {
+                "config_name": "player_tank",
+                "pos": [100.0, 100.0],
+                "direction": "Up",
+                "player_id": 0,
+                "hp": 40.0,
+                "shoot_elapsed": 0.0625,
+                "lives": 2,
+                "invulnerable_elapsed": 0.5
+            },
+            {
+                "config_name": "ai_tank",
+                "pos": [600.0, 600.0],
+                "direction": "Down",
+                "hp": 10.0,
+                "shoot_elapsed": 0.5,
+                "spawner": 0,
+                "ai": {
+                    "action": "Move",
+                    "collision_happened": true,
+                    "keep_direction_elapsed": 0.5,
+                    "move_elapsed": 1.0,
+                    "shoot_elapsed": 0.25
+                }
+            },
+            {
+                "config_name": "player_tank",
+                "pos": [200.0, 100.0],
+                "direction": "Up",
+                "player_id": 1,
+                "lives": 1,
+                "respawn_elapsed": 0.75
+            }
+        ],
+        "phasing_objs": [
+            {
+                "config_name": "player_tank",
+                "pos": [200.0, 300.0],
+                "direction": "Left",
+                "elapsed": 0.0625,
+                "player_id": 1
+            },
+            {
+                "config_name": "player_tank",
+                "pos": [300.0, 300.0],
+                "direction": "Right",
+                "elapsed": 0.125,
+                "player_id": 2,
+                "team": "Rival"
+            }
+        ],
+        "spawners": [
+            {
+                "config_name": "ai_tank",
+                "pos": [600.0, 700.0],
+                "direction": "Down",
+                "count": 3,
+                "interval": 2.0,
+                "max_alive": 2,
+                "spawned": 1,
+                "elapsed": 0.5
+            }
+        ],
+        "sim_tick": 42,
+        "seed": 9
+    });
+    let save_path = std::env::temp_dir().join("tank_rs_test_save.json");
+    std::fs::write(&save_path, saved.to_string()).unwrap();
+
+    let mut app = create_headless_app(3);
+    app.update();
+    let world = app.world_mut();
+    world
+        .run_system_once(move |mut reloader: GameWorldReloader| {
+            reloader.reload(&save_path, GameMode::Coop)
+        })
+        .unwrap()
+        .unwrap();
+    let map_config: GameMapConfig = world
+        .run_system_once(|save_params: SaveParams| save_params.get_save_config())
+        .unwrap();
+
+    // Objs come back in entity order, which the reload may reshuffle
+    let sort_objs = |mut map_config: serde_json::Value| {
+        for key in ["objs", "phasing_objs"] {
+            map_config[key]
+                .as_array_mut()
+                .unwrap()
+                .sort_by_key(|obj| obj.to_string());
+        }
+        map_config
+    };
+    assert_eq!(
+        sort_objs(serde_json::to_value(&map_config).unwrap()),
+        sort_objs(saved)
+    );
+
+    // The dying players lost their lives before the save and still count
+    // until they are gone
+    app.update();
+    let player_info = app.world().resource::<PlayerInfo>();
+    assert_eq!(player_info.len(), 3);
+    assert_eq!(player_info[&1].lives, 1);
+    assert_eq!(player_info[&2].team, 1);
+    assert_eq!(
+        *app.world().resource::<State<GameState>>().get(),
+        GameState::Playing
+    );
+}